@@ -0,0 +1,89 @@
+// Generates the ftrace event table (src/ftrace/event_table.rs) from the
+// `ftrace_event::Event` variants and their messages in perfetto.protos.rs, so
+// new events in the protos show up without anyone touching the table.
+
+use std::{env, fs, path::Path};
+
+// the proto prefixes events of vendor groups whose names clash with others
+const SPECIAL: &[(&str, &str)] = &[
+    ("Zero", "0"),
+    ("MaliMaliKcpuCqsSet", "mali_KCPU_CQS_SET"),
+    ("MaliMaliKcpuCqsWaitStart", "mali_KCPU_CQS_WAIT_START"),
+    ("MaliMaliKcpuCqsWaitEnd", "mali_KCPU_CQS_WAIT_END"),
+    ("MaliMaliKcpuFenceSignal", "mali_KCPU_FENCE_SIGNAL"),
+    ("MaliMaliKcpuFenceWaitStart", "mali_KCPU_FENCE_WAIT_START"),
+    ("MaliMaliKcpuFenceWaitEnd", "mali_KCPU_FENCE_WAIT_END"),
+    ("MaliMaliCsfInterruptStart", "mali_CSF_INTERRUPT_START"),
+    ("MaliMaliCsfInterruptEnd", "mali_CSF_INTERRUPT_END"),
+];
+
+fn ident(s: &str) -> &str {
+    let s = s.trim_start();
+    &s[..s.find(|c: char| !c.is_alphanumeric() && c != '_' && c != '#').unwrap_or(s.len())]
+}
+
+fn snake(name: &str) -> String {
+    let mut out = String::new();
+    for (i, c) in name.chars().enumerate() {
+        if c.is_ascii_uppercase() && i > 0 {
+            out.push('_');
+        }
+        out.push(c.to_ascii_lowercase());
+    }
+    out
+}
+
+fn event_name(variant: &str) -> String {
+    match SPECIAL.iter().find(|(v, _)| *v == variant) {
+        Some((_, name)) => name.to_string(),
+        None if variant.ends_with("TracingMarkWrite") => "tracing_mark_write".to_string(),
+        None => snake(variant),
+    }
+}
+
+// (variant, message) of every event in the oneof
+fn variants(protos: &str) -> Vec<(&str, &str)> {
+    let module = &protos[protos.find("pub mod ftrace_event {").expect("no ftrace_event module")..];
+    let body = &module[module.find("pub enum Event {").unwrap()..];
+    let body = &body[..body.find("\n    }\n").unwrap()];
+    body.split("#[prost(message, tag = \"").skip(1).map(|chunk| {
+        let rest = &chunk[chunk.find(")]").unwrap() + 2..];
+        (ident(rest), ident(&rest[rest.find("super::").unwrap() + 7..]))
+    }).collect()
+}
+
+// (field, repeated) of a top level message
+fn fields<'a>(protos: &'a str, message: &str) -> Vec<(&'a str, bool)> {
+    let start = protos.find(&format!("\npub struct {} {{", message)).unwrap_or_else(|| panic!("no message {}", message));
+    let body = &protos[start + 1..];
+    let body = &body[..body.find("\n}").unwrap_or(body.len())];
+    let body = &body[..body.find("\npub ").unwrap_or(body.len())];
+    body.split("#[prost(").skip(1).filter_map(|chunk| {
+        let end = chunk.find(")]")?;
+        let field = chunk[end + 2..].trim_start().strip_prefix("pub ")?;
+        Some((ident(field), chunk[..end].contains("repeated")))
+    }).collect()
+}
+
+fn main() {
+    let protos_path = "src/perfetto.protos.rs";
+    println!("cargo:rerun-if-changed={}", protos_path);
+    println!("cargo:rerun-if-changed=build.rs");
+    let protos = fs::read_to_string(protos_path).unwrap();
+    let mut out = String::new();
+    out.push_str("/// The name of the event and its fields that are set, None for\n/// `GenericFtraceEvent` which has its own.\n");
+    out.push_str("pub fn fields(event: &Event) -> Option<(&'static str, Vec<(&'static str, String)>)> {\n");
+    out.push_str("    let (name, fields): (_, Vec<(_, Option<String>)>) = match event {\n");
+    for (variant, message) in variants(&protos) {
+        if variant == "Generic" {
+            out.push_str("        Event::Generic(_) => return None,\n");
+            continue;
+        }
+        let fields: Vec<_> = fields(&protos, message).into_iter().map(|(field, repeated)| format!("(\"{}\", {}(&e.{}))", field.trim_start_matches("r#"), if repeated { "list" } else { "value" }, field)).collect();
+        out.push_str(&format!("        Event::{}({}) => (\"{}\", vec![{}]),\n", variant, if fields.is_empty() { "_" } else { "e" }, event_name(variant), fields.join(", ")));
+    }
+    out.push_str("    };\n");
+    out.push_str("    Some((name, fields.into_iter().filter_map(|(name, value)| Some((name, value?))).collect()))\n");
+    out.push_str("}\n");
+    fs::write(Path::new(&env::var("OUT_DIR").unwrap()).join("event_table.rs"), out).unwrap();
+}
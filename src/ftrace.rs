@@ -0,0 +1,190 @@
+use std::collections::HashMap;
+
+use crate::model::Model;
+use crate::perfetto::{ftrace_event::Event, FtraceEvent, PrintFtraceEvent};
+
+mod binder;
+mod block;
+mod counters;
+mod event_table;
+mod funcgraph;
+mod gpu;
+mod irq;
+mod kvm;
+mod memory;
+mod network;
+mod power;
+mod syscall_table;
+mod syscalls;
+mod task;
+pub(crate) mod text;
+mod workqueue;
+
+#[derive(Default)]
+pub struct Ftrace {
+    // print every event as trace_pipe would
+    pub trace_pipe: bool,
+    // open atrace slices per pid
+    print_state: HashMap<u32, Vec<(u64, String)>>,
+    irq: irq::Irq,
+    binder: binder::Binder,
+    pub workqueue: workqueue::Workqueue,
+    memory: memory::Memory,
+    pub syscalls: syscalls::Syscalls,
+    power: power::Power,
+    pub gpu: gpu::Gpu,
+    pub funcgraph: funcgraph::Funcgraph,
+    pub network: network::Network,
+    pub kvm: kvm::Kvm,
+    // InternedData.kernel_symbols by (sequence, iid), function addresses are
+    // replaced by these iids when traced_probes symbolizes them
+    pub kernel_symbols: HashMap<(u32, u64), String>,
+    pub block: block::Block,
+}
+
+impl Ftrace {
+    /// `timestamp` is in the boot clock, events are expected in timestamp order.
+    /// `function` is what `function` gave when the event was read.
+    pub fn event(&mut self, model: &mut Model, timestamp: u64, cpu: u32, e: FtraceEvent, function: Option<String>) {
+        let pid = e.pid.unwrap();
+        task::names(model, timestamp, pid, &e);
+        if self.trace_pipe {
+            let comm = match pid {
+                0 => "<idle>",
+                _ => model.threads.comm(pid, timestamp).unwrap_or("<...>"),
+            };
+            println!("{}", text::trace_pipe_line(cpu, comm, &e));
+        }
+        match e.event {
+            Some(Event::Print(ftrace_print)) => self.print(model, timestamp, pid, ftrace_print),
+            Some(Event::CpuFrequency(e)) => counters::cpu_frequency(model, timestamp, e),
+            Some(Event::CpuFrequencyLimits(e)) => counters::cpu_frequency_limits(model, timestamp, e),
+            Some(Event::CpuIdle(e)) => counters::cpu_idle(model, timestamp, e),
+            Some(Event::ThermalTemperature(e)) => counters::thermal_temperature(model, timestamp, e),
+            Some(Event::GpuFrequency(e)) => counters::gpu_frequency(model, timestamp, e),
+            Some(Event::IrqHandlerEntry(e)) => self.irq.irq_entry(timestamp, cpu, pid, e),
+            Some(Event::IrqHandlerExit(e)) => self.irq.irq_exit(model, timestamp, cpu, e),
+            Some(Event::SoftirqRaise(e)) => self.irq.softirq_raise(model, timestamp, cpu, pid, e),
+            Some(Event::SoftirqEntry(e)) => self.irq.softirq_entry(timestamp, cpu, pid, e),
+            Some(Event::SoftirqExit(e)) => self.irq.softirq_exit(model, timestamp, cpu, e),
+            Some(Event::IpiRaise(e)) => self.irq.ipi_raise(model, timestamp, cpu, pid, e),
+            Some(Event::IpiEntry(e)) => self.irq.ipi_entry(timestamp, cpu, pid, e),
+            Some(Event::IpiExit(e)) => self.irq.ipi_exit(model, timestamp, cpu, e),
+            Some(Event::BlockRqIssue(e)) => self.block.rq_issue(timestamp, pid, e),
+            Some(Event::BlockRqComplete(e)) => self.block.rq_complete(model, timestamp, e),
+            Some(Event::Ext4SyncFileEnter(e)) => self.block.ext4_sync_file_enter(timestamp, pid, e),
+            Some(Event::Ext4SyncFileExit(e)) => self.block.ext4_sync_file_exit(model, timestamp, pid, e),
+            Some(Event::F2fsSyncFileEnter(e)) => self.block.f2fs_sync_file_enter(timestamp, pid, e),
+            Some(Event::F2fsSyncFileExit(e)) => self.block.f2fs_sync_file_exit(model, timestamp, pid, e),
+            Some(Event::AndroidFsDatareadStart(e)) => self.block.android_fs_dataread_start(timestamp, pid, e),
+            Some(Event::AndroidFsDatareadEnd(e)) => self.block.android_fs_dataread_end(model, timestamp, e),
+            Some(Event::UfshcdCommand(e)) => self.block.ufshcd_command(model, timestamp, pid, e),
+            Some(Event::BinderTransaction(e)) => self.binder.transaction(model, timestamp, pid, e),
+            Some(Event::BinderTransactionReceived(e)) => self.binder.transaction_received(model, timestamp, pid, e),
+            Some(Event::BinderTransactionAllocBuf(e)) => self.binder.transaction_alloc_buf(e),
+            Some(Event::BinderReturn(e)) => self.binder.binder_return(model, timestamp, pid, e),
+            Some(Event::BinderLock(e)) => self.binder.lock(timestamp, pid, e),
+            Some(Event::BinderLocked(e)) => self.binder.locked(model, timestamp, pid, e),
+            Some(Event::BinderUnlock(e)) => self.binder.unlock(model, timestamp, pid, e),
+            Some(Event::WorkqueueQueueWork(e)) => self.workqueue.queue_work(timestamp, e),
+            Some(Event::WorkqueueActivateWork(e)) => self.workqueue.activate_work(timestamp, e),
+            Some(Event::WorkqueueExecuteStart(e)) => self.workqueue.execute_start(timestamp, pid, function.unwrap_or_default(), e),
+            Some(Event::WorkqueueExecuteEnd(e)) => self.workqueue.execute_end(model, timestamp, pid, e),
+            Some(Event::MmVmscanDirectReclaimBegin(e)) => self.memory.direct_reclaim_begin(timestamp, pid, e),
+            Some(Event::MmVmscanDirectReclaimEnd(e)) => self.memory.direct_reclaim_end(model, timestamp, pid, e),
+            Some(Event::MmCompactionBegin(e)) => self.memory.compaction_begin(timestamp, pid, e),
+            Some(Event::MmCompactionEnd(e)) => self.memory.compaction_end(model, timestamp, pid, e),
+            Some(Event::LowmemoryKill(e)) => self.memory.lowmemory_kill(model, timestamp, pid, e),
+            Some(Event::MarkVictim(e)) => self.memory.mark_victim(model, timestamp, pid, e),
+            Some(Event::OomScoreAdjUpdate(e)) => self.memory.oom_score_adj_update(model, timestamp, e),
+            Some(Event::RssStat(e)) => self.memory.rss_stat(model, timestamp, pid, e),
+            Some(Event::SysEnter(e)) => self.syscalls.sys_enter(timestamp, pid, e),
+            Some(Event::SysExit(e)) => self.syscalls.sys_exit(model, timestamp, pid, e),
+            Some(Event::SuspendResume(e)) => self.power.suspend_resume(model, timestamp, pid, e),
+            Some(Event::SuspendResumeMinimal(e)) => self.power.suspend_resume_minimal(model, timestamp, pid, e),
+            Some(Event::WakeupSourceActivate(e)) => self.power.wakeup_source_activate(timestamp, pid, e),
+            Some(Event::WakeupSourceDeactivate(e)) => self.power.wakeup_source_deactivate(model, timestamp, e),
+            Some(Event::ClkEnable(e)) => self.power.clk_enable(model, timestamp, e),
+            Some(Event::ClkDisable(e)) => self.power.clk_disable(model, timestamp, e),
+            Some(Event::ClkSetRate(e)) => self.power.clk_set_rate(model, timestamp, e),
+            Some(Event::ClockEnable(e)) => self.power.clock_enable(model, timestamp, e),
+            Some(Event::ClockDisable(e)) => self.power.clock_disable(model, timestamp, e),
+            Some(Event::ClockSetRate(e)) => self.power.clock_set_rate(model, timestamp, e),
+            Some(Event::GpuWorkPeriod(e)) => self.gpu.work_period(model, e),
+            Some(Event::DmaFenceInit(e)) => self.gpu.dma_fence_init(e),
+            Some(Event::DmaFenceEmit(e)) => self.gpu.dma_fence_emit(timestamp, pid, e),
+            Some(Event::DmaFenceSignaled(e)) => self.gpu.dma_fence_signaled(model, timestamp, pid, e),
+            Some(Event::DmaFenceWaitStart(e)) => self.gpu.dma_fence_wait_start(timestamp, pid, e),
+            Some(Event::DmaFenceWaitEnd(e)) => self.gpu.dma_fence_wait_end(model, timestamp, pid, e),
+            Some(Event::DrmSchedJob(e)) => self.gpu.drm_sched_job(timestamp, e),
+            Some(Event::DrmRunJob(e)) => self.gpu.drm_run_job(timestamp, e),
+            Some(Event::DrmSchedProcessJob(e)) => self.gpu.drm_sched_process_job(model, timestamp, pid, e),
+            Some(Event::MaliMaliKcpuCqsSet(e)) => self.gpu.mali_kcpu_cqs_set(model, timestamp, e),
+            Some(Event::MaliMaliKcpuCqsWaitStart(e)) => self.gpu.mali_kcpu_cqs_wait_start(timestamp, e),
+            Some(Event::MaliMaliKcpuCqsWaitEnd(e)) => self.gpu.mali_kcpu_cqs_wait_end(model, timestamp, e),
+            Some(Event::MaliMaliKcpuFenceSignal(e)) => self.gpu.mali_kcpu_fence_signal(model, timestamp, e),
+            Some(Event::MaliMaliKcpuFenceWaitStart(e)) => self.gpu.mali_kcpu_fence_wait_start(timestamp, e),
+            Some(Event::MaliMaliKcpuFenceWaitEnd(e)) => self.gpu.mali_kcpu_fence_wait_end(model, timestamp, e),
+            Some(Event::FuncgraphEntry(e)) => self.funcgraph.entry(timestamp, cpu, pid, function.unwrap_or_default(), e),
+            Some(Event::FuncgraphExit(e)) => self.funcgraph.exit(model, timestamp, pid, e),
+            Some(Event::NetDevXmit(e)) => self.network.net_dev_xmit(model, timestamp, e),
+            Some(Event::NetifReceiveSkb(e)) => self.network.netif_receive_skb(model, timestamp, e),
+            Some(Event::InetSockSetState(e)) => self.network.inet_sock_set_state(model, timestamp, pid, e),
+            Some(Event::TcpRetransmitSkb(e)) => self.network.tcp_retransmit_skb(model, timestamp, pid, e),
+            Some(Event::KvmEntry(e)) => self.kvm.entry(model, timestamp, pid, e),
+            Some(Event::KvmExit(e)) => self.kvm.exit(model, timestamp, pid, e),
+            Some(Event::HypEnter(e)) => self.kvm.hyp_enter(timestamp, cpu, pid, e),
+            Some(Event::HypExit(e)) => self.kvm.hyp_exit(model, timestamp, cpu, e),
+            Some(Event::HostHcall(e)) => self.kvm.host_hcall(cpu, e),
+            Some(Event::HostSmc(e)) => self.kvm.host_smc(cpu, e),
+            Some(Event::HostMemAbort(e)) => self.kvm.host_mem_abort(cpu, e),
+            _ => (),
+        }
+    }
+
+    /// The kernel function of the events that have one, named by the symbols
+    /// interned on the sequence. Looked up as the event is read, the sequence
+    /// can clear them before the sorter lets it go.
+    pub fn function(&self, sequence_id: u32, e: &FtraceEvent) -> Option<String> {
+        let function = match &e.event {
+            Some(Event::WorkqueueExecuteStart(e)) => e.function(),
+            Some(Event::FuncgraphEntry(e)) => e.func(),
+            _ => return None,
+        };
+        match self.kernel_symbols.get(&(sequence_id, function)) {
+            Some(name) => Some(name.clone()),
+            None => Some(format!("{:#x}", function)),
+        }
+    }
+
+    fn print(&mut self, model: &mut Model, timestamp: u64, pid: u32, ftrace_print: PrintFtraceEvent) {
+        let buf = ftrace_print.buf.as_ref().unwrap();
+        // See ParseSystraceTracePoint in perfetto for how to parse these things
+
+        // drop new line at the end
+        let buf = buf.strip_suffix('\n').unwrap_or(buf);
+        // B|1356|prepareSurfaces
+        let mut pieces = buf.split('|');
+
+        let phase = pieces.next().unwrap().chars().next().unwrap();
+        if let Some(tgid) = pieces.next().and_then(|tgid| tgid.parse().ok()) {
+            model.threads.tgid_seen(pid, timestamp, tgid);
+        }
+
+        let state = self.print_state.entry(pid).or_default();
+        match phase {
+            'B' => {
+                let msg = pieces.next().unwrap();
+                state.push((timestamp, msg.to_owned()));
+            },
+            'E' => {
+                if let Some((start, msg)) = state.pop() {
+                    model.slice(pid, "ftrace", start, timestamp, &msg);
+                } else {
+                    eprintln!("missing start for {}", timestamp)
+                }
+            },
+            _ => (),
+        }
+    }
+}
@@ -0,0 +1,177 @@
+//! Binder transactions, modelled after perfetto's BinderTracker.
+//!
+//! The client gets a "binder transaction" slice from sending the transaction
+//! until the reply arrives, the server a "binder reply" slice from receiving
+//! it until it sends the reply back. Flows link the two in both directions.
+//! One way transactions are instants on both ends.
+
+use std::collections::HashMap;
+
+use crate::model::Model;
+use crate::perfetto::{BinderLockFtraceEvent, BinderLockedFtraceEvent, BinderReturnFtraceEvent, BinderTransactionAllocBufFtraceEvent, BinderTransactionFtraceEvent, BinderTransactionReceivedFtraceEvent, BinderUnlockFtraceEvent};
+
+// include/uapi/linux/android/binder.h
+const TF_ONE_WAY: u32 = 0x01;
+const BR_DEAD_REPLY: u32 = 5;
+const BR_FAILED_REPLY: u32 = 17;
+
+struct Transaction {
+    start: u64,
+    tid: u32,
+    name: String,
+}
+
+#[derive(Default)]
+pub struct Binder {
+    // transactions sent but not received yet, by debug id
+    in_flight: HashMap<i32, (Transaction, bool)>,
+    // replies sent but not received yet, by debug id
+    replies: HashMap<i32, Transaction>,
+    // per thread stacks of clients waiting for a reply and servers working on one
+    waiting: HashMap<u32, Vec<Transaction>>,
+    serving: HashMap<u32, Vec<Transaction>>,
+    // when each thread started waiting for and got the global binder lock
+    lock_waiting: HashMap<u32, u64>,
+    lock_held: HashMap<u32, u64>,
+}
+
+impl Binder {
+    pub fn transaction(&mut self, model: &mut Model, timestamp: u64, tid: u32, e: BinderTransactionFtraceEvent) {
+        if e.reply() != 0 {
+            if let Some(serving) = self.serving.get_mut(&tid).and_then(|s| s.pop()) {
+                model.slice(tid, "binder", serving.start, timestamp, &serving.name);
+            }
+            let name = format!("binder reply debug_id={}", e.debug_id());
+            self.replies.insert(e.debug_id(), Transaction { start: timestamp, tid, name });
+            return;
+        }
+        let oneway = e.flags() & TF_ONE_WAY != 0;
+        let name = format!(
+            "binder transaction{} debug_id={} dest={}:{} node={} code={:#x} flags={:#x}",
+            if oneway { " async" } else { "" },
+            e.debug_id(), e.to_proc(), e.to_thread(), e.target_node(), e.code(), e.flags()
+        );
+        if oneway {
+            model.instant(tid, "binder", timestamp, &name);
+        } else {
+            self.waiting.entry(tid).or_default().push(Transaction { start: timestamp, tid, name: name.clone() });
+        }
+        self.in_flight.insert(e.debug_id(), (Transaction { start: timestamp, tid, name }, oneway));
+    }
+
+    pub fn transaction_received(&mut self, model: &mut Model, timestamp: u64, tid: u32, e: BinderTransactionReceivedFtraceEvent) {
+        if let Some(reply) = self.replies.remove(&e.debug_id()) {
+            // we're the client, the wait is over
+            if let Some(waiting) = self.waiting.get_mut(&tid).and_then(|w| w.pop()) {
+                model.slice(tid, "binder", waiting.start, timestamp, &waiting.name);
+            }
+            model.flow(reply.tid, reply.start, tid, timestamp, &reply.name);
+        } else if let Some((transaction, oneway)) = self.in_flight.remove(&e.debug_id()) {
+            model.flow(transaction.tid, transaction.start, tid, timestamp, &transaction.name);
+            let name = format!("binder {} debug_id={} from={}", if oneway { "async rcv" } else { "reply" }, e.debug_id(), transaction.tid);
+            if oneway {
+                model.instant(tid, "binder", timestamp, &name);
+            } else {
+                self.serving.entry(tid).or_default().push(Transaction { start: timestamp, tid, name });
+            }
+        }
+    }
+
+    // the buffer is allocated right after the transaction event, add its size
+    pub fn transaction_alloc_buf(&mut self, e: BinderTransactionAllocBufFtraceEvent) {
+        let size = format!(" data_size={} offsets_size={}", e.data_size(), e.offsets_size());
+        if let Some((transaction, _)) = self.in_flight.get_mut(&e.debug_id()) {
+            if let Some(waiting) = self.waiting.get_mut(&transaction.tid).and_then(|w| w.last_mut()) {
+                if waiting.start == transaction.start {
+                    waiting.name += &size;
+                }
+            }
+            transaction.name += &size;
+        }
+    }
+
+    pub fn binder_return(&mut self, model: &mut Model, timestamp: u64, tid: u32, e: BinderReturnFtraceEvent) {
+        let nr = e.cmd() & 0xff;
+        if nr == BR_DEAD_REPLY || nr == BR_FAILED_REPLY {
+            if let Some(waiting) = self.waiting.get_mut(&tid).and_then(|w| w.pop()) {
+                let status = if nr == BR_DEAD_REPLY { "dead reply" } else { "failed reply" };
+                model.slice(tid, "binder", waiting.start, timestamp, &format!("{} {}", waiting.name, status));
+            }
+        }
+    }
+
+    pub fn lock(&mut self, timestamp: u64, tid: u32, _e: BinderLockFtraceEvent) {
+        self.lock_waiting.insert(tid, timestamp);
+    }
+
+    pub fn locked(&mut self, model: &mut Model, timestamp: u64, tid: u32, e: BinderLockedFtraceEvent) {
+        if let Some(start) = self.lock_waiting.remove(&tid) {
+            model.slice(tid, "binder.lock", start, timestamp, &format!("binder lock waiting {}", e.tag()));
+        }
+        self.lock_held.insert(tid, timestamp);
+    }
+
+    pub fn unlock(&mut self, model: &mut Model, timestamp: u64, tid: u32, e: BinderUnlockFtraceEvent) {
+        if let Some(start) = self.lock_held.remove(&tid) {
+            model.slice(tid, "binder.lock", start, timestamp, &format!("binder lock held {}", e.tag()));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn transaction(debug_id: i32, reply: bool, flags: u32) -> BinderTransactionFtraceEvent {
+        BinderTransactionFtraceEvent { debug_id: Some(debug_id), reply: Some(reply as i32), flags: Some(flags), ..Default::default() }
+    }
+
+    fn received(debug_id: i32) -> BinderTransactionReceivedFtraceEvent {
+        BinderTransactionReceivedFtraceEvent { debug_id: Some(debug_id) }
+    }
+
+    fn depth(stacks: &HashMap<u32, Vec<Transaction>>, tid: u32) -> usize {
+        stacks.get(&tid).map_or(0, Vec::len)
+    }
+
+    #[test]
+    fn reply_matches_transaction() {
+        let mut model = Model { quiet: true, ..Default::default() };
+        let mut binder = Binder::default();
+        // client 10 calls server 20, which calls 30 while serving it
+        binder.transaction(&mut model, 10, 10, transaction(1, false, 0));
+        binder.transaction_alloc_buf(BinderTransactionAllocBufFtraceEvent { debug_id: Some(1), data_size: Some(64), ..Default::default() });
+        assert!(binder.waiting[&10][0].name.ends_with("data_size=64 offsets_size=0"));
+        binder.transaction_received(&mut model, 20, 20, received(1));
+        binder.transaction(&mut model, 30, 20, transaction(2, false, 0));
+        binder.transaction_received(&mut model, 40, 30, received(2));
+        assert_eq!((depth(&binder.waiting, 10), depth(&binder.waiting, 20)), (1, 1));
+        assert_eq!((depth(&binder.serving, 20), depth(&binder.serving, 30)), (1, 1));
+        assert!(binder.in_flight.is_empty());
+        // the replies come back innermost first
+        binder.transaction(&mut model, 50, 30, transaction(3, true, 0));
+        assert_eq!(depth(&binder.serving, 30), 0);
+        binder.transaction_received(&mut model, 60, 20, received(3));
+        assert_eq!(depth(&binder.waiting, 20), 0);
+        assert_eq!(depth(&binder.serving, 20), 1);
+        binder.transaction(&mut model, 70, 20, transaction(4, true, 0));
+        binder.transaction_received(&mut model, 80, 10, received(4));
+        assert_eq!((depth(&binder.waiting, 10), depth(&binder.serving, 20)), (0, 0));
+        assert!(binder.replies.is_empty());
+    }
+
+    #[test]
+    fn one_way_and_failed() {
+        let mut model = Model { quiet: true, ..Default::default() };
+        let mut binder = Binder::default();
+        // nobody waits for or serves a one way transaction
+        binder.transaction(&mut model, 10, 10, transaction(1, false, TF_ONE_WAY));
+        binder.transaction_received(&mut model, 20, 20, received(1));
+        assert_eq!((depth(&binder.waiting, 10), depth(&binder.serving, 20)), (0, 0));
+        assert!(binder.in_flight.is_empty());
+        // the server died, the client is told instead of getting a reply
+        binder.transaction(&mut model, 30, 10, transaction(2, false, 0));
+        binder.binder_return(&mut model, 40, 10, BinderReturnFtraceEvent { cmd: Some(0x8000_7200 | BR_DEAD_REPLY) });
+        assert_eq!(depth(&binder.waiting, 10), 0);
+    }
+}
@@ -0,0 +1,113 @@
+//! Block I/O and filesystem latency: block requests are matched by dev and
+//! sector, fsyncs by the thread doing them, android_fs reads by inode and
+//! offset and ufs commands by host and tag.
+
+use std::collections::{BTreeMap, HashMap};
+
+use crate::histogram::Histogram;
+use crate::model::Model;
+use crate::perfetto::{AndroidFsDatareadEndFtraceEvent, AndroidFsDatareadStartFtraceEvent, BlockRqCompleteFtraceEvent, BlockRqIssueFtraceEvent, Ext4SyncFileEnterFtraceEvent, Ext4SyncFileExitFtraceEvent, F2fsSyncFileEnterFtraceEvent, F2fsSyncFileExitFtraceEvent, UfshcdCommandFtraceEvent};
+
+// the kernel's internal dev_t is 12 bits of major and 20 bits of minor
+fn dev_name(dev: u64) -> String {
+    format!("{}:{}", dev >> 20, dev & 0xfffff)
+}
+
+struct Request {
+    start: u64,
+    tid: u32,
+    name: String,
+}
+
+#[derive(Default)]
+pub struct Block {
+    requests: HashMap<(u64, u64), Request>,
+    syncs: HashMap<u32, Request>,
+    reads: HashMap<(u64, i64), Request>,
+    ufs: HashMap<(String, u32), Request>,
+    // latency per device, keyed by track, e.g. "block.259:0"
+    latencies: BTreeMap<String, Histogram>,
+}
+
+impl Block {
+    fn complete(&mut self, model: &mut Model, timestamp: u64, track: String, request: Request, name: &str) {
+        model.slice(request.tid, &track, request.start, timestamp, name);
+        self.latencies.entry(track).or_default().add(timestamp.saturating_sub(request.start));
+    }
+
+    pub fn rq_issue(&mut self, timestamp: u64, tid: u32, e: BlockRqIssueFtraceEvent) {
+        let name = format!("{} {} + {} bytes={} comm={}", e.rwbs(), e.sector(), e.nr_sector(), e.bytes(), e.comm());
+        self.requests.insert((e.dev(), e.sector()), Request { start: timestamp, tid, name });
+    }
+
+    pub fn rq_complete(&mut self, model: &mut Model, timestamp: u64, e: BlockRqCompleteFtraceEvent) {
+        if let Some(request) = self.requests.remove(&(e.dev(), e.sector())) {
+            let mut name = request.name.clone();
+            let error = e.error.or(e.errors).unwrap_or(0);
+            if error != 0 {
+                name += &format!(" error={}", error);
+            }
+            self.complete(model, timestamp, format!("block.{}", dev_name(e.dev())), request, &name);
+        }
+    }
+
+    pub fn ext4_sync_file_enter(&mut self, timestamp: u64, tid: u32, e: Ext4SyncFileEnterFtraceEvent) {
+        let name = format!("fsync ino={} datasync={}", e.ino(), e.datasync());
+        self.syncs.insert(tid, Request { start: timestamp, tid, name });
+    }
+
+    pub fn ext4_sync_file_exit(&mut self, model: &mut Model, timestamp: u64, tid: u32, e: Ext4SyncFileExitFtraceEvent) {
+        if let Some(request) = self.syncs.remove(&tid) {
+            let name = format!("{} ret={}", request.name, e.ret());
+            self.complete(model, timestamp, format!("ext4.{}", dev_name(e.dev())), request, &name);
+        }
+    }
+
+    pub fn f2fs_sync_file_enter(&mut self, timestamp: u64, tid: u32, e: F2fsSyncFileEnterFtraceEvent) {
+        let name = format!("fsync ino={} size={}", e.ino(), e.size());
+        self.syncs.insert(tid, Request { start: timestamp, tid, name });
+    }
+
+    pub fn f2fs_sync_file_exit(&mut self, model: &mut Model, timestamp: u64, tid: u32, e: F2fsSyncFileExitFtraceEvent) {
+        if let Some(request) = self.syncs.remove(&tid) {
+            let name = format!("{} datasync={} need_cp={} ret={}", request.name, e.datasync(), e.need_cp(), e.ret());
+            self.complete(model, timestamp, format!("f2fs.{}", dev_name(e.dev())), request, &name);
+        }
+    }
+
+    pub fn android_fs_dataread_start(&mut self, timestamp: u64, tid: u32, e: AndroidFsDatareadStartFtraceEvent) {
+        let name = format!("read {} offset={} bytes={} cmdline={}", e.pathbuf(), e.offset(), e.bytes(), e.cmdline());
+        self.reads.insert((e.ino(), e.offset()), Request { start: timestamp, tid, name });
+    }
+
+    pub fn android_fs_dataread_end(&mut self, model: &mut Model, timestamp: u64, e: AndroidFsDatareadEndFtraceEvent) {
+        if let Some(request) = self.reads.remove(&(e.ino(), e.offset())) {
+            let name = request.name.clone();
+            self.complete(model, timestamp, "android_fs.dataread".to_owned(), request, &name);
+        }
+    }
+
+    pub fn ufshcd_command(&mut self, model: &mut Model, timestamp: u64, tid: u32, e: UfshcdCommandFtraceEvent) {
+        let key = (e.dev_name().to_owned(), e.tag());
+        // older kernels give a string ("send_req", "complete_rsp"), newer ones an enum
+        let (send, complete) = match &e.str {
+            Some(s) => (s.starts_with("send"), s.starts_with("complete")),
+            None => (e.str_t() == 0, e.str_t() == 1),
+        };
+        if send {
+            let name = format!("opcode={:#x} lba={} len={} tag={}", e.opcode(), e.lba(), e.transfer_len(), e.tag());
+            self.ufs.insert(key, Request { start: timestamp, tid, name });
+        } else if complete {
+            if let Some(request) = self.ufs.remove(&key) {
+                let name = request.name.clone();
+                self.complete(model, timestamp, format!("ufs.{}", e.dev_name()), request, &name);
+            }
+        }
+    }
+
+    pub fn print_latencies(&self) {
+        for (device, histogram) in &self.latencies {
+            print!("{} {}", device, histogram);
+        }
+    }
+}
@@ -0,0 +1,37 @@
+//! Counter tracks that come straight out of a single ftrace event: cpu
+//! frequency and idle states, thermal zones and gpu frequency.
+
+use crate::model::Model;
+use crate::perfetto::{CpuFrequencyFtraceEvent, CpuFrequencyLimitsFtraceEvent, CpuIdleFtraceEvent, GpuFrequencyFtraceEvent, ThermalTemperatureFtraceEvent};
+
+pub fn cpu_frequency(model: &mut Model, timestamp: u64, e: CpuFrequencyFtraceEvent) {
+    // kHz
+    model.counter(format!("cpu{}.freq", e.cpu_id()), timestamp, e.state() as f64);
+}
+
+pub fn cpu_frequency_limits(model: &mut Model, timestamp: u64, e: CpuFrequencyLimitsFtraceEvent) {
+    model.counter(format!("cpu{}.freq_min", e.cpu_id()), timestamp, e.min_freq() as f64);
+    model.counter(format!("cpu{}.freq_max", e.cpu_id()), timestamp, e.max_freq() as f64);
+}
+
+pub fn cpu_idle(model: &mut Model, timestamp: u64, e: CpuIdleFtraceEvent) {
+    // the kernel reports leaving idle as state (u32)-1
+    let state = match e.state() {
+        u32::MAX => -1.,
+        state => state as f64,
+    };
+    model.counter(format!("cpu{}.idle", e.cpu_id()), timestamp, state);
+}
+
+pub fn thermal_temperature(model: &mut Model, timestamp: u64, e: ThermalTemperatureFtraceEvent) {
+    // millidegrees Celsius
+    let track = match &e.thermal_zone {
+        Some(zone) => format!("thermal.{}", zone),
+        None => format!("thermal.zone{}", e.id()),
+    };
+    model.counter(track, timestamp, e.temp() as f64);
+}
+
+pub fn gpu_frequency(model: &mut Model, timestamp: u64, e: GpuFrequencyFtraceEvent) {
+    model.counter(format!("gpu{}.freq", e.gpu_id()), timestamp, e.state() as f64);
+}
@@ -0,0 +1,19 @@
+//! The kernel's name and the fields of every typed ftrace event. build.rs
+//! generates `fields` from the `ftrace_event::Event` variants and their
+//! messages in perfetto.protos.rs, so regenerating the protos is all it takes
+//! to pick up new events. The names are the proto's field names, except for
+//! the vendor tracing_mark_write and mali events the proto prefixes with
+//! their group.
+
+use crate::perfetto::ftrace_event::Event;
+
+fn value<T: ToString>(value: &Option<T>) -> Option<String> {
+    value.as_ref().map(T::to_string)
+}
+
+fn list<T: ToString>(values: &[T]) -> Option<String> {
+    (!values.is_empty()).then(|| format!("[{}]", values.iter().map(T::to_string).collect::<Vec<_>>().join(", ")))
+}
+
+// fn fields(&Event) -> Option<(name, fields that are set)>
+include!(concat!(env!("OUT_DIR"), "/event_table.rs"));
@@ -0,0 +1,66 @@
+//! function_graph tracer entries and exits as nested kernel call slices on
+//! per cpu tracks, and the time spent in each call stack folded up for flame
+//! graphs. The tracer keeps its return stack per task, a call into schedule()
+//! returns on whatever cpu the task is woken on, so stacks are kept per thread.
+
+use std::collections::{BTreeMap, HashMap};
+
+use crate::model::Model;
+use crate::perfetto::{FuncgraphEntryFtraceEvent, FuncgraphExitFtraceEvent};
+
+struct Call {
+    func: u64,
+    depth: i32,
+    name: String,
+    start: u64,
+    cpu: u32,
+    // time spent in calls made from this one
+    children: u64,
+}
+
+#[derive(Default)]
+pub struct Funcgraph {
+    stacks: HashMap<u32, Vec<Call>>,
+    // self time per ;-joined stack, comm first
+    folded: BTreeMap<String, u64>,
+}
+
+impl Funcgraph {
+    pub fn entry(&mut self, timestamp: u64, cpu: u32, tid: u32, name: String, e: FuncgraphEntryFtraceEvent) {
+        let call = Call { func: e.func(), depth: e.depth(), name, start: timestamp, cpu, children: 0 };
+        self.stacks.entry(tid).or_default().push(call);
+    }
+
+    pub fn exit(&mut self, model: &mut Model, timestamp: u64, tid: u32, e: FuncgraphExitFtraceEvent) {
+        let stack = self.stacks.entry(tid).or_default();
+        // entries lost to overruns leave calls that never see their exit,
+        // drop them. An exit without its entry is from before the trace started.
+        let Some(i) = stack.iter().rposition(|call| call.func == e.func() && call.depth == e.depth()) else {
+            return;
+        };
+        stack.truncate(i + 1);
+        let call = stack.pop().unwrap();
+        let duration = timestamp.saturating_sub(call.start);
+        if let Some(parent) = stack.last_mut() {
+            parent.children += duration;
+        }
+
+        let comm = model.threads.comm(tid, call.start).unwrap_or("<...>");
+        let mut path = comm.replace(' ', "_");
+        for frame in stack.iter().chain([&call]) {
+            path.push(';');
+            path.push_str(&frame.name);
+        }
+        *self.folded.entry(path).or_default() += duration.saturating_sub(call.children);
+
+        model.slice(tid, &format!("cpu{}.funcgraph", call.cpu), call.start, timestamp, &call.name);
+    }
+
+    /// One `comm;outer;...;inner self_ns` line per stack, what flamegraph.pl
+    /// and most other flame graph tools take.
+    pub fn print_folded(&self) {
+        for (path, time) in &self.folded {
+            println!("{} {}", path, time);
+        }
+    }
+}
@@ -0,0 +1,170 @@
+//! GPU queues and fences. Jobs and work periods become slices on per queue
+//! "gpu." tracks, dma fences get a slice from being emitted until they signal,
+//! and threads waiting on a fence get a wait slice with a flow from whoever
+//! signaled it. Also takes the render stages of `GpuRenderStageEvent` packets,
+//! which aren't ftrace but land on the same tracks.
+
+use std::collections::HashMap;
+
+use crate::histogram::format_duration;
+use crate::model::Model;
+use crate::perfetto::{DmaFenceEmitFtraceEvent, DmaFenceInitFtraceEvent, DmaFenceSignaledFtraceEvent, DmaFenceWaitEndFtraceEvent, DmaFenceWaitStartFtraceEvent, DrmRunJobFtraceEvent, DrmSchedJobFtraceEvent, DrmSchedProcessJobFtraceEvent, GpuRenderStageEvent, GpuWorkPeriodFtraceEvent, MaliMaliKcpucqssetFtraceEvent, MaliMaliKcpucqswaitendFtraceEvent, MaliMaliKcpucqswaitstartFtraceEvent, MaliMaliKcpufencesignalFtraceEvent, MaliMaliKcpufencewaitendFtraceEvent, MaliMaliKcpufencewaitstartFtraceEvent};
+
+struct Fence {
+    name: String,
+    emitted: Option<(u64, u32)>,
+    signaled: Option<(u64, u32)>,
+}
+
+struct Job {
+    queued: u64,
+    // when it started running and on which ring
+    running: Option<(u64, String)>,
+    name: String,
+}
+
+#[derive(Default)]
+pub struct Gpu {
+    // by (context, seqno)
+    fences: HashMap<(u32, u32), Fence>,
+    // per thread (start, context, seqno)
+    fence_waits: HashMap<u32, (u64, u32, u32)>,
+    // drm scheduler jobs by finished fence
+    jobs: HashMap<u64, Job>,
+    // mali kcpu waits by (kctx_id, id, cqs or fence)
+    kcpu_waits: HashMap<(u32, u32, &'static str), (u64, u32)>,
+    // InternedData.gpu_specifications by (sequence, iid), names of hw queues
+    // and render stages
+    pub specifications: HashMap<(u32, u64), String>,
+}
+
+impl Gpu {
+    pub fn work_period(&mut self, model: &mut Model, e: GpuWorkPeriodFtraceEvent) {
+        let name = format!("work period uid={} active={}", e.uid(), format_duration(e.total_active_duration_ns()));
+        model.slice(0, &format!("gpu{}.work_period", e.gpu_id()), e.start_time_ns(), e.end_time_ns(), &name);
+    }
+
+    fn fence(&mut self, context: u32, seqno: u32, driver: &str, timeline: &str) -> &mut Fence {
+        self.fences.entry((context, seqno)).or_insert_with(|| Fence {
+            name: format!("{} {} {}", driver, timeline, seqno),
+            emitted: None,
+            signaled: None,
+        })
+    }
+
+    pub fn dma_fence_init(&mut self, e: DmaFenceInitFtraceEvent) {
+        // a new fence on the same context and seqno starts over
+        self.fences.remove(&(e.context(), e.seqno()));
+        self.fence(e.context(), e.seqno(), e.driver(), e.timeline());
+    }
+
+    pub fn dma_fence_emit(&mut self, timestamp: u64, tid: u32, e: DmaFenceEmitFtraceEvent) {
+        self.fence(e.context(), e.seqno(), e.driver(), e.timeline()).emitted = Some((timestamp, tid));
+    }
+
+    pub fn dma_fence_signaled(&mut self, model: &mut Model, timestamp: u64, tid: u32, e: DmaFenceSignaledFtraceEvent) {
+        let fence = self.fence(e.context(), e.seqno(), e.driver(), e.timeline());
+        fence.signaled = Some((timestamp, tid));
+        if let Some((start, emitter)) = fence.emitted.take() {
+            let name = fence.name.clone();
+            model.slice(emitter, &format!("gpu.fence.{}", e.timeline()), start, timestamp, &name);
+        }
+    }
+
+    pub fn dma_fence_wait_start(&mut self, timestamp: u64, tid: u32, e: DmaFenceWaitStartFtraceEvent) {
+        self.fence(e.context(), e.seqno(), e.driver(), e.timeline());
+        self.fence_waits.insert(tid, (timestamp, e.context(), e.seqno()));
+    }
+
+    pub fn dma_fence_wait_end(&mut self, model: &mut Model, timestamp: u64, tid: u32, _e: DmaFenceWaitEndFtraceEvent) {
+        let Some((start, context, seqno)) = self.fence_waits.remove(&tid) else {
+            return;
+        };
+        let Some(fence) = self.fences.get(&(context, seqno)) else {
+            return;
+        };
+        model.slice(tid, "gpu.fence_wait", start, timestamp, &format!("fence wait {}", fence.name));
+        if let Some((signaled, signaler)) = fence.signaled {
+            model.flow(signaler, signaled, tid, timestamp, &fence.name);
+        }
+    }
+
+    pub fn drm_sched_job(&mut self, timestamp: u64, e: DrmSchedJobFtraceEvent) {
+        let name = format!("job id={} entity={:#x}", e.id(), e.entity());
+        self.jobs.insert(e.fence(), Job { queued: timestamp, running: None, name });
+    }
+
+    pub fn drm_run_job(&mut self, timestamp: u64, e: DrmRunJobFtraceEvent) {
+        let job = self.jobs.entry(e.fence()).or_insert_with(|| Job {
+            queued: timestamp,
+            running: None,
+            name: format!("job id={} entity={:#x}", e.id(), e.entity()),
+        });
+        job.running = Some((timestamp, e.name().to_owned()));
+    }
+
+    pub fn drm_sched_process_job(&mut self, model: &mut Model, timestamp: u64, tid: u32, e: DrmSchedProcessJobFtraceEvent) {
+        if let Some(Job { queued, running: Some((start, ring)), name }) = self.jobs.remove(&e.fence()) {
+            let name = format!("{} queued={}", name, format_duration(start.saturating_sub(queued)));
+            model.slice(tid, &format!("gpu.{}", ring), start, timestamp, &name);
+        }
+    }
+
+    fn kcpu_wait_start(&mut self, timestamp: u64, kind: &'static str, kctx_tgid: i32, kctx_id: u32, id: u32) {
+        self.kcpu_waits.insert((kctx_id, id, kind), (timestamp, kctx_tgid as u32));
+    }
+
+    fn kcpu_wait_end(&mut self, model: &mut Model, timestamp: u64, kind: &'static str, kctx_id: u32, id: u32) {
+        if let Some((start, tgid)) = self.kcpu_waits.remove(&(kctx_id, id, kind)) {
+            let name = format!("{} wait kctx={} id={}", kind, kctx_id, id);
+            model.slice(tgid, &format!("gpu.mali.kcpu.{}", kctx_id), start, timestamp, &name);
+        }
+    }
+
+    pub fn mali_kcpu_cqs_set(&mut self, model: &mut Model, timestamp: u64, e: MaliMaliKcpucqssetFtraceEvent) {
+        model.instant(e.kctx_tgid() as u32, &format!("gpu.mali.kcpu.{}", e.kctx_id()), timestamp, &format!("cqs set id={}", e.id()));
+    }
+
+    pub fn mali_kcpu_cqs_wait_start(&mut self, timestamp: u64, e: MaliMaliKcpucqswaitstartFtraceEvent) {
+        self.kcpu_wait_start(timestamp, "cqs", e.kctx_tgid(), e.kctx_id(), e.id());
+    }
+
+    pub fn mali_kcpu_cqs_wait_end(&mut self, model: &mut Model, timestamp: u64, e: MaliMaliKcpucqswaitendFtraceEvent) {
+        self.kcpu_wait_end(model, timestamp, "cqs", e.kctx_id(), e.id());
+    }
+
+    pub fn mali_kcpu_fence_signal(&mut self, model: &mut Model, timestamp: u64, e: MaliMaliKcpufencesignalFtraceEvent) {
+        model.instant(e.kctx_tgid() as u32, &format!("gpu.mali.kcpu.{}", e.kctx_id()), timestamp, &format!("fence signal id={}", e.id()));
+    }
+
+    pub fn mali_kcpu_fence_wait_start(&mut self, timestamp: u64, e: MaliMaliKcpufencewaitstartFtraceEvent) {
+        self.kcpu_wait_start(timestamp, "fence", e.kctx_tgid(), e.kctx_id(), e.id());
+    }
+
+    pub fn mali_kcpu_fence_wait_end(&mut self, model: &mut Model, timestamp: u64, e: MaliMaliKcpufencewaitendFtraceEvent) {
+        self.kcpu_wait_end(model, timestamp, "fence", e.kctx_id(), e.id());
+    }
+
+    fn specification(&self, sequence_id: u32, iid: Option<u64>, id: Option<i32>, kind: &str) -> String {
+        match iid.and_then(|iid| self.specifications.get(&(sequence_id, iid))) {
+            Some(name) => name.clone(),
+            None => format!("{}{}", kind, id.unwrap_or(0)),
+        }
+    }
+
+    /// The names of the hw queue and the stage of a render stage, looked up
+    /// as it's read since the sequence can clear them before it's sorted.
+    // older producers only set the deprecated hw_queue_id and stage_id
+    #[allow(deprecated)]
+    pub fn render_stage_names(&self, sequence_id: u32, e: &GpuRenderStageEvent) -> (String, String) {
+        // the track name goes in a space separated column
+        let queue = self.specification(sequence_id, e.hw_queue_iid, e.hw_queue_id, "queue").replace(' ', "_");
+        (queue, self.specification(sequence_id, e.stage_iid, e.stage_id, "stage"))
+    }
+
+    /// `timestamp` is the packet's, in the boot clock.
+    pub fn render_stage(&mut self, model: &mut Model, timestamp: u64, e: GpuRenderStageEvent, queue: String, stage: String) {
+        let name = format!("{} context={:#x} submission={}", stage, e.context(), e.submission_id());
+        model.slice(0, &format!("gpu{}.{}", e.gpu_id(), queue), timestamp, timestamp + e.duration(), &name);
+    }
+}
@@ -0,0 +1,94 @@
+//! Hard irq, softirq and ipi slices. Each cpu gets its own track, the tid of
+//! a slice is the thread that was interrupted (the pid ftrace records for the
+//! entry event is whatever was current on that cpu).
+
+use std::collections::HashMap;
+
+use crate::model::Model;
+use crate::perfetto::{IpiEntryFtraceEvent, IpiExitFtraceEvent, IpiRaiseFtraceEvent, IrqHandlerEntryFtraceEvent, IrqHandlerExitFtraceEvent, SoftirqEntryFtraceEvent, SoftirqExitFtraceEvent, SoftirqRaiseFtraceEvent};
+
+// include/linux/interrupt.h
+const SOFTIRQ_NAMES: [&str; 10] = ["HI", "TIMER", "NET_TX", "NET_RX", "BLOCK", "IRQ_POLL", "TASKLET", "SCHED", "HRTIMER", "RCU"];
+
+fn softirq_name(vec: u32) -> String {
+    match SOFTIRQ_NAMES.get(vec as usize) {
+        Some(name) => name.to_string(),
+        None => format!("vec{}", vec),
+    }
+}
+
+struct Open {
+    start: u64,
+    tid: u32,
+    name: String,
+}
+
+#[derive(Default)]
+pub struct Irq {
+    // per cpu stacks of open slices
+    irqs: HashMap<u32, Vec<(i32, Open)>>,
+    softirqs: HashMap<u32, Vec<(u32, Open)>>,
+    ipis: HashMap<u32, Vec<Open>>,
+    // when each (cpu, vec) softirq was last raised
+    softirq_raised: HashMap<(u32, u32), u64>,
+}
+
+impl Irq {
+    pub fn irq_entry(&mut self, timestamp: u64, cpu: u32, tid: u32, e: IrqHandlerEntryFtraceEvent) {
+        let name = format!("irq={} {}", e.irq(), e.name());
+        self.irqs.entry(cpu).or_default().push((e.irq(), Open { start: timestamp, tid, name }));
+    }
+
+    pub fn irq_exit(&mut self, model: &mut Model, timestamp: u64, cpu: u32, e: IrqHandlerExitFtraceEvent) {
+        let stack = self.irqs.entry(cpu).or_default();
+        match stack.pop() {
+            Some((irq, open)) if irq == e.irq() => {
+                let handled = if e.ret() == 1 { "handled" } else { "unhandled" };
+                let name = format!("{} {}", open.name, handled);
+                model.slice(open.tid, &format!("cpu{}.irq", cpu), open.start, timestamp, &name);
+            },
+            Some((irq, _)) => eprintln!("irq exit for {} on cpu{} while in irq {}", e.irq(), cpu, irq),
+            None => (),
+        }
+    }
+
+    pub fn softirq_raise(&mut self, model: &mut Model, timestamp: u64, cpu: u32, tid: u32, e: SoftirqRaiseFtraceEvent) {
+        self.softirq_raised.insert((cpu, e.vec()), timestamp);
+        let name = format!("raise vec={} {}", e.vec(), softirq_name(e.vec()));
+        model.instant(tid, &format!("cpu{}.softirq", cpu), timestamp, &name);
+    }
+
+    pub fn softirq_entry(&mut self, timestamp: u64, cpu: u32, tid: u32, e: SoftirqEntryFtraceEvent) {
+        let mut name = format!("vec={} {}", e.vec(), softirq_name(e.vec()));
+        if let Some(raised) = self.softirq_raised.remove(&(cpu, e.vec())) {
+            name += &format!(" raised={}ns", timestamp.saturating_sub(raised));
+        }
+        self.softirqs.entry(cpu).or_default().push((e.vec(), Open { start: timestamp, tid, name }));
+    }
+
+    pub fn softirq_exit(&mut self, model: &mut Model, timestamp: u64, cpu: u32, e: SoftirqExitFtraceEvent) {
+        let stack = self.softirqs.entry(cpu).or_default();
+        match stack.pop() {
+            Some((vec, open)) if vec == e.vec() => {
+                model.slice(open.tid, &format!("cpu{}.softirq", cpu), open.start, timestamp, &open.name);
+            },
+            Some((vec, _)) => eprintln!("softirq exit for {} on cpu{} while in softirq {}", e.vec(), cpu, vec),
+            None => (),
+        }
+    }
+
+    pub fn ipi_raise(&mut self, model: &mut Model, timestamp: u64, cpu: u32, tid: u32, e: IpiRaiseFtraceEvent) {
+        let name = format!("raise target_cpus={:#x} {}", e.target_cpus(), e.reason());
+        model.instant(tid, &format!("cpu{}.ipi", cpu), timestamp, &name);
+    }
+
+    pub fn ipi_entry(&mut self, timestamp: u64, cpu: u32, tid: u32, e: IpiEntryFtraceEvent) {
+        self.ipis.entry(cpu).or_default().push(Open { start: timestamp, tid, name: e.reason().to_owned() });
+    }
+
+    pub fn ipi_exit(&mut self, model: &mut Model, timestamp: u64, cpu: u32, _e: IpiExitFtraceEvent) {
+        if let Some(open) = self.ipis.entry(cpu).or_default().pop() {
+            model.slice(open.tid, &format!("cpu{}.ipi", cpu), open.start, timestamp, &open.name);
+        }
+    }
+}
@@ -0,0 +1,161 @@
+//! Virtualization on arm64. kvm_entry/kvm_exit split each vcpu thread into
+//! time in the guest and time back in the host handling the exit, on the
+//! "kvm.vcpu" track. The pKVM hyp_enter/hyp_exit events give per cpu slices of
+//! time in the hypervisor, named by the hcall, smc or abort that caused it.
+//! Host and hypervisor time is also kept in histograms per exit reason for
+//! the `kvm` view.
+
+use std::collections::{BTreeMap, HashMap};
+
+use crate::histogram::Histogram;
+use crate::model::Model;
+use crate::perfetto::{HostHcallFtraceEvent, HostMemAbortFtraceEvent, HostSmcFtraceEvent, HypEnterFtraceEvent, HypExitFtraceEvent, KvmEntryFtraceEvent, KvmExitFtraceEvent};
+
+// arch/arm64/include/asm/kvm_asm.h
+const ARM_EXCEPTION_IRQ: u32 = 0;
+const ARM_EXCEPTION_EL1_SERROR: u32 = 1;
+const ARM_EXCEPTION_TRAP: u32 = 2;
+const ARM_EXCEPTION_IL: u32 = 3;
+const ARM_EXIT_WITH_SERROR_BIT: u32 = 31;
+
+// ESR_ELx_EC_* from arch/arm64/include/asm/esr.h
+fn exception_class(ec: u32) -> String {
+    let name = match ec {
+        0x00 => "UNKNOWN",
+        0x01 => "WFx",
+        0x03 => "CP15_32",
+        0x04 => "CP15_64",
+        0x05 => "CP14_MR",
+        0x06 => "CP14_LS",
+        0x07 => "FP_ASIMD",
+        0x08 => "CP10_ID",
+        0x09 => "PAC",
+        0x0c => "CP14_64",
+        0x0d => "BTI",
+        0x0e => "ILL",
+        0x11 => "SVC32",
+        0x12 => "HVC32",
+        0x13 => "SMC32",
+        0x15 => "SVC64",
+        0x16 => "HVC64",
+        0x17 => "SMC64",
+        0x18 => "SYS64",
+        0x19 => "SVE",
+        0x1a => "ERET",
+        0x1c => "FPAC",
+        0x1d => "SME",
+        0x1f => "IMP_DEF",
+        0x20 => "IABT_LOW",
+        0x21 => "IABT_CUR",
+        0x22 => "PC_ALIGN",
+        0x24 => "DABT_LOW",
+        0x25 => "DABT_CUR",
+        0x26 => "SP_ALIGN",
+        0x27 => "MOPS",
+        0x28 => "FP_EXC32",
+        0x2c => "FP_EXC64",
+        0x2f => "SERROR",
+        0x30 => "BREAKPT_LOW",
+        0x31 => "BREAKPT_CUR",
+        0x32 => "SOFTSTP_LOW",
+        0x33 => "SOFTSTP_CUR",
+        0x34 => "WATCHPT_LOW",
+        0x35 => "WATCHPT_CUR",
+        0x38 => "BKPT32",
+        0x3a => "VECTOR32",
+        0x3c => "BRK64",
+        _ => return format!("EC_{:#x}", ec),
+    };
+    name.to_owned()
+}
+
+fn exit_reason(e: &KvmExitFtraceEvent) -> String {
+    match e.ret() as u32 & !(1 << ARM_EXIT_WITH_SERROR_BIT) {
+        ARM_EXCEPTION_IRQ => "IRQ".to_owned(),
+        ARM_EXCEPTION_EL1_SERROR => "SERROR".to_owned(),
+        ARM_EXCEPTION_TRAP => exception_class(e.esr_ec()),
+        ARM_EXCEPTION_IL => "IL".to_owned(),
+        ret => format!("exception{}", ret),
+    }
+}
+
+enum Vcpu {
+    // in the guest since, entered at pc
+    Guest(u64, u64),
+    // back in the host since, handling reason
+    Host(u64, String),
+}
+
+#[derive(Default)]
+pub struct Kvm {
+    // per vcpu thread
+    vcpus: HashMap<u32, Vcpu>,
+    // per cpu (start, tid, reason) while in the hypervisor
+    hyp: HashMap<u32, (u64, u32, Option<String>)>,
+    exits: BTreeMap<String, Histogram>,
+    hyp_calls: BTreeMap<String, Histogram>,
+}
+
+impl Kvm {
+    pub fn entry(&mut self, model: &mut Model, timestamp: u64, tid: u32, e: KvmEntryFtraceEvent) {
+        if let Some(Vcpu::Host(start, reason)) = self.vcpus.remove(&tid) {
+            model.slice(tid, "kvm.vcpu", start, timestamp, &format!("host {}", reason));
+            self.exits.entry(reason).or_default().add(timestamp.saturating_sub(start));
+        }
+        self.vcpus.insert(tid, Vcpu::Guest(timestamp, e.vcpu_pc()));
+    }
+
+    pub fn exit(&mut self, model: &mut Model, timestamp: u64, tid: u32, e: KvmExitFtraceEvent) {
+        let reason = exit_reason(&e);
+        if let Some(Vcpu::Guest(start, pc)) = self.vcpus.remove(&tid) {
+            let name = format!("guest pc={:#x} exit={} exit_pc={:#x}", pc, reason, e.vcpu_pc());
+            model.slice(tid, "kvm.vcpu", start, timestamp, &name);
+        }
+        self.vcpus.insert(tid, Vcpu::Host(timestamp, reason));
+    }
+
+    pub fn hyp_enter(&mut self, timestamp: u64, cpu: u32, tid: u32, _e: HypEnterFtraceEvent) {
+        self.hyp.insert(cpu, (timestamp, tid, None));
+    }
+
+    pub fn hyp_exit(&mut self, model: &mut Model, timestamp: u64, cpu: u32, _e: HypExitFtraceEvent) {
+        if let Some((start, tid, reason)) = self.hyp.remove(&cpu) {
+            let reason = reason.unwrap_or_else(|| "hyp".to_owned());
+            model.slice(tid, &format!("cpu{}.hyp", cpu), start, timestamp, &reason);
+            // the ids and addresses make every call unique, histogram by kind
+            let kind = reason.split_whitespace().next().unwrap().to_owned();
+            self.hyp_calls.entry(kind).or_default().add(timestamp.saturating_sub(start));
+        }
+    }
+
+    fn hyp_reason(&mut self, cpu: u32, reason: String) {
+        if let Some((_, _, current)) = self.hyp.get_mut(&cpu) {
+            *current = Some(reason);
+        }
+    }
+
+    pub fn host_hcall(&mut self, cpu: u32, e: HostHcallFtraceEvent) {
+        let invalid = if e.invalid() != 0 { " invalid" } else { "" };
+        self.hyp_reason(cpu, format!("hcall id={:#x}{}", e.id(), invalid));
+    }
+
+    pub fn host_smc(&mut self, cpu: u32, e: HostSmcFtraceEvent) {
+        let forwarded = if e.forwarded() != 0 { " forwarded" } else { "" };
+        self.hyp_reason(cpu, format!("smc id={:#x}{}", e.id(), forwarded));
+    }
+
+    pub fn host_mem_abort(&mut self, cpu: u32, e: HostMemAbortFtraceEvent) {
+        self.hyp_reason(cpu, format!("mem_abort esr={:#x} addr={:#x}", e.esr(), e.addr()));
+    }
+
+    pub fn print_latencies(&self) {
+        println!("host time per kvm exit reason");
+        for (reason, histogram) in &self.exits {
+            print!("{} {}", reason, histogram);
+        }
+        println!("hypervisor time per host call");
+        for (kind, histogram) in &self.hyp_calls {
+            print!("{} {}", kind, histogram);
+        }
+    }
+}
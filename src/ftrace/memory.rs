@@ -0,0 +1,120 @@
+//! Memory pressure: direct reclaim and compaction slices per thread, low
+//! memory and oom kills as instants, and per process rss and oom_score_adj
+//! counters. Everything goes on "mem." tracks so the `memory` view can pick
+//! them out.
+
+use std::collections::HashMap;
+
+use crate::model::Model;
+use crate::perfetto::{LowmemoryKillFtraceEvent, MarkVictimFtraceEvent, MmCompactionBeginFtraceEvent, MmCompactionEndFtraceEvent, MmVmscanDirectReclaimBeginFtraceEvent, MmVmscanDirectReclaimEndFtraceEvent, OomScoreAdjUpdateFtraceEvent, RssStatFtraceEvent};
+
+// enum of NR_MM_COUNTERS in include/linux/mm_types_task.h
+const RSS_MEMBERS: [&str; 4] = ["mem.rss.file", "mem.rss.anon", "mem.swap", "mem.rss.shmem"];
+
+// include/linux/compaction.h
+fn compaction_status(status: i32) -> &'static str {
+    match status {
+        0 => "not_suitable_zone",
+        1 => "skipped",
+        2 => "deferred",
+        3 => "no_suitable_page",
+        4 => "continue",
+        5 => "complete",
+        6 => "partial_skipped",
+        7 => "contended",
+        8 => "success",
+        _ => "unknown",
+    }
+}
+
+#[derive(Default)]
+pub struct Memory {
+    reclaim: HashMap<u32, (u64, String)>,
+    compaction: HashMap<u32, u64>,
+    // rss_stat only has the mm for other processes, remember whose it was,
+    // by pid
+    mm_owners: HashMap<u32, u32>,
+}
+
+impl Memory {
+    pub fn direct_reclaim_begin(&mut self, timestamp: u64, tid: u32, e: MmVmscanDirectReclaimBeginFtraceEvent) {
+        let name = format!("direct reclaim order={} gfp_flags={:#x}", e.order(), e.gfp_flags());
+        self.reclaim.insert(tid, (timestamp, name));
+    }
+
+    pub fn direct_reclaim_end(&mut self, model: &mut Model, timestamp: u64, tid: u32, e: MmVmscanDirectReclaimEndFtraceEvent) {
+        if let Some((start, name)) = self.reclaim.remove(&tid) {
+            model.slice(tid, "mem.reclaim", start, timestamp, &format!("{} nr_reclaimed={}", name, e.nr_reclaimed()));
+        }
+    }
+
+    pub fn compaction_begin(&mut self, timestamp: u64, tid: u32, _e: MmCompactionBeginFtraceEvent) {
+        self.compaction.insert(tid, timestamp);
+    }
+
+    pub fn compaction_end(&mut self, model: &mut Model, timestamp: u64, tid: u32, e: MmCompactionEndFtraceEvent) {
+        if let Some(start) = self.compaction.remove(&tid) {
+            let name = format!("compaction sync={} status={}", e.sync(), compaction_status(e.status()));
+            model.slice(tid, "mem.compaction", start, timestamp, &name);
+        }
+    }
+
+    pub fn lowmemory_kill(&mut self, model: &mut Model, timestamp: u64, tid: u32, e: LowmemoryKillFtraceEvent) {
+        let name = format!("lmk kill pid={} comm={} pagecache={} free={}", e.pid(), e.comm(), e.pagecache_size(), e.free());
+        model.instant(tid, "mem.kill", timestamp, &name);
+    }
+
+    pub fn mark_victim(&mut self, model: &mut Model, timestamp: u64, tid: u32, e: MarkVictimFtraceEvent) {
+        let comm = model.threads.comm(e.pid() as u32, timestamp).unwrap_or("<...>").to_owned();
+        model.instant(tid, "mem.kill", timestamp, &format!("oom kill pid={} comm={}", e.pid(), comm));
+    }
+
+    pub fn oom_score_adj_update(&mut self, model: &mut Model, timestamp: u64, e: OomScoreAdjUpdateFtraceEvent) {
+        model.counter(format!("mem.oom_score_adj.{}", e.pid()), timestamp, e.oom_score_adj() as f64);
+    }
+
+    pub fn rss_stat(&mut self, model: &mut Model, timestamp: u64, tid: u32, e: RssStatFtraceEvent) {
+        // older kernels don't have mm_id and curr, it's always the current task
+        // then. The mm is the process's, not the thread's that faulted.
+        let pid = model.threads.tgid(tid, timestamp).unwrap_or(tid);
+        let pid = match (e.mm_id, e.curr()) {
+            (Some(mm_id), 0) => match self.mm_owners.get(&mm_id) {
+                Some(&pid) => pid,
+                None => return,
+            },
+            (Some(mm_id), _) => {
+                self.mm_owners.insert(mm_id, pid);
+                pid
+            },
+            (None, _) => pid,
+        };
+        if let Some(member) = RSS_MEMBERS.get(e.member() as usize) {
+            model.counter(format!("{}.{}", member, pid), timestamp, e.size() as f64);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn rss_stat(member: i32, size: i64, mm_id: Option<u32>, curr: u32) -> RssStatFtraceEvent {
+        RssStatFtraceEvent { member: Some(member), size: Some(size), mm_id, curr: Some(curr) }
+    }
+
+    #[test]
+    fn rss_is_per_process() {
+        let mut model = Model { quiet: true, ..Default::default() };
+        model.threads.tgid_seen(101, 0, 100);
+        let mut memory = Memory::default();
+        // a thread faulting in its own process's memory, then someone else
+        // changing it through the mm
+        memory.rss_stat(&mut model, 10, 101, rss_stat(1, 4096, Some(7), 1));
+        memory.rss_stat(&mut model, 20, 5, rss_stat(1, 8192, Some(7), 0));
+        // older kernels
+        memory.rss_stat(&mut model, 30, 101, rss_stat(0, 1024, None, 0));
+        let tracks: Vec<_> = model.counters.keys().collect();
+        assert_eq!(tracks, ["mem.rss.anon.100", "mem.rss.file.100"]);
+        assert_eq!(model.counters["mem.rss.anon.100"], [(10, 4096.), (20, 8192.)]);
+    }
+}
@@ -0,0 +1,95 @@
+//! Network traffic and tcp connections. Bytes sent and received per interface
+//! from the net tracepoints and per uid from `NetworkPacketEvent` packets, as
+//! running totals on "net." counters. Tcp sockets get a slice for every state
+//! they go through, and retransmits are instants next to them.
+
+use std::collections::HashMap;
+use std::net::Ipv4Addr;
+
+use crate::model::Model;
+use crate::perfetto::{InetSockSetStateFtraceEvent, NetDevXmitFtraceEvent, NetifReceiveSkbFtraceEvent, NetworkPacketEvent, TcpRetransmitSkbFtraceEvent, TrafficDirection};
+
+// include/uapi/linux/in.h
+const IPPROTO_TCP: u32 = 6;
+const AF_INET: u32 = 2;
+
+// include/net/tcp_states.h
+const TCP_STATES: [&str; 13] = ["", "ESTABLISHED", "SYN_SENT", "SYN_RECV", "FIN_WAIT1", "FIN_WAIT2", "TIME_WAIT", "CLOSE", "CLOSE_WAIT", "LAST_ACK", "LISTEN", "CLOSING", "NEW_SYN_RECV"];
+const TCP_CLOSE: i32 = 7;
+
+fn tcp_state(state: i32) -> String {
+    match TCP_STATES.get(state as usize).filter(|name| !name.is_empty()) {
+        Some(name) => name.to_string(),
+        None => format!("state{}", state),
+    }
+}
+
+// the tracepoints copy the address bytes as they are in memory
+fn endpoint(family: u32, addr: u32, port: u32) -> String {
+    match family {
+        AF_INET => format!("{}:{}", Ipv4Addr::from(addr.to_le_bytes()), port),
+        // only the ipv4 address is in the protos
+        _ => format!("[v6]:{}", port),
+    }
+}
+
+fn connection(family: u32, saddr: u32, sport: u32, daddr: u32, dport: u32) -> String {
+    format!("{} -> {}", endpoint(family, saddr, sport), endpoint(family, daddr, dport))
+}
+
+#[derive(Default)]
+pub struct Network {
+    // running totals behind the byte counters
+    bytes: HashMap<String, u64>,
+    // current state of each tcp socket, (since, tid, state)
+    sockets: HashMap<u64, (u64, u32, i32)>,
+}
+
+impl Network {
+    fn add_bytes(&mut self, model: &mut Model, timestamp: u64, track: String, bytes: u64) {
+        let total = self.bytes.entry(track.clone()).or_default();
+        *total += bytes;
+        model.counter(track, timestamp, *total as f64);
+    }
+
+    pub fn net_dev_xmit(&mut self, model: &mut Model, timestamp: u64, e: NetDevXmitFtraceEvent) {
+        // anything but NETDEV_TX_OK wasn't sent
+        if e.rc() == 0 {
+            self.add_bytes(model, timestamp, format!("net.{}.tx_bytes", e.name()), e.len().into());
+        }
+    }
+
+    pub fn netif_receive_skb(&mut self, model: &mut Model, timestamp: u64, e: NetifReceiveSkbFtraceEvent) {
+        self.add_bytes(model, timestamp, format!("net.{}.rx_bytes", e.name()), e.len().into());
+    }
+
+    /// A packet or an aggregate of packets from the network_packet data
+    /// source, `length` is the total for all of them.
+    pub fn packet(&mut self, model: &mut Model, timestamp: u64, e: &NetworkPacketEvent, length: u64) {
+        let direction = match e.direction() {
+            TrafficDirection::DirIngress => "rx",
+            TrafficDirection::DirEgress => "tx",
+            TrafficDirection::DirUnspecified => return,
+        };
+        self.add_bytes(model, timestamp, format!("net.uid.{}.{}_bytes", e.uid(), direction), length);
+    }
+
+    pub fn inet_sock_set_state(&mut self, model: &mut Model, timestamp: u64, tid: u32, e: InetSockSetStateFtraceEvent) {
+        if e.protocol() != IPPROTO_TCP {
+            return;
+        }
+        if let Some((start, tid, state)) = self.sockets.remove(&e.skaddr()) {
+            let name = format!("{} {}", tcp_state(state), connection(e.family(), e.saddr(), e.sport(), e.daddr(), e.dport()));
+            model.slice(tid, "net.tcp", start, timestamp, &name);
+        }
+        // the socket address gets reused once it's closed
+        if e.newstate() != TCP_CLOSE {
+            self.sockets.insert(e.skaddr(), (timestamp, tid, e.newstate()));
+        }
+    }
+
+    pub fn tcp_retransmit_skb(&mut self, model: &mut Model, timestamp: u64, tid: u32, e: TcpRetransmitSkbFtraceEvent) {
+        let name = format!("retransmit {} {}", tcp_state(e.state()), connection(AF_INET, e.saddr(), e.sport(), e.daddr(), e.dport()));
+        model.instant(tid, "net.tcp", timestamp, &name);
+    }
+}
@@ -0,0 +1,72 @@
+//! Suspend/resume phases and wakelocks as slices, clocks as rate and enable
+//! counters. All on "power." tracks for the `power` view.
+
+use std::collections::HashMap;
+
+use crate::model::Model;
+use crate::perfetto::{ClkDisableFtraceEvent, ClkEnableFtraceEvent, ClkSetRateFtraceEvent, ClockDisableFtraceEvent, ClockEnableFtraceEvent, ClockSetRateFtraceEvent, SuspendResumeFtraceEvent, SuspendResumeMinimalFtraceEvent, WakeupSourceActivateFtraceEvent, WakeupSourceDeactivateFtraceEvent};
+
+#[derive(Default)]
+pub struct Power {
+    // open suspend/resume phases by action, they nest (suspend_enter contains
+    // dpm_suspend and so on) but each action only once
+    phases: HashMap<String, (u64, u32, i32)>,
+    suspended: Option<(u64, u32)>,
+    // active wakeup sources by name
+    wakelocks: HashMap<String, (u64, u32)>,
+}
+
+impl Power {
+    pub fn suspend_resume(&mut self, model: &mut Model, timestamp: u64, tid: u32, e: SuspendResumeFtraceEvent) {
+        let action = e.action().to_owned();
+        if e.start() != 0 {
+            self.phases.insert(action, (timestamp, tid, e.val()));
+        } else if let Some((start, tid, val)) = self.phases.remove(&action) {
+            model.slice(tid, "power.suspend_resume", start, timestamp, &format!("{}({})", action, val));
+        }
+    }
+
+    // only records the time actually spent suspended
+    pub fn suspend_resume_minimal(&mut self, model: &mut Model, timestamp: u64, tid: u32, e: SuspendResumeMinimalFtraceEvent) {
+        if e.start() != 0 {
+            self.suspended = Some((timestamp, tid));
+        } else if let Some((start, tid)) = self.suspended.take() {
+            model.slice(tid, "power.suspend_resume", start, timestamp, "suspended");
+        }
+    }
+
+    pub fn wakeup_source_activate(&mut self, timestamp: u64, tid: u32, e: WakeupSourceActivateFtraceEvent) {
+        self.wakelocks.entry(e.name().to_owned()).or_insert((timestamp, tid));
+    }
+
+    pub fn wakeup_source_deactivate(&mut self, model: &mut Model, timestamp: u64, e: WakeupSourceDeactivateFtraceEvent) {
+        if let Some((start, tid)) = self.wakelocks.remove(e.name()) {
+            model.slice(tid, "power.wakelock", start, timestamp, e.name());
+        }
+    }
+
+    pub fn clk_enable(&mut self, model: &mut Model, timestamp: u64, e: ClkEnableFtraceEvent) {
+        model.counter(format!("power.clock.{}.enabled", e.name()), timestamp, 1.);
+    }
+
+    pub fn clk_disable(&mut self, model: &mut Model, timestamp: u64, e: ClkDisableFtraceEvent) {
+        model.counter(format!("power.clock.{}.enabled", e.name()), timestamp, 0.);
+    }
+
+    pub fn clk_set_rate(&mut self, model: &mut Model, timestamp: u64, e: ClkSetRateFtraceEvent) {
+        model.counter(format!("power.clock.{}.rate", e.name()), timestamp, e.rate() as f64);
+    }
+
+    // the older power:clock_* events
+    pub fn clock_enable(&mut self, model: &mut Model, timestamp: u64, e: ClockEnableFtraceEvent) {
+        model.counter(format!("power.clock.{}.enabled", e.name()), timestamp, e.state() as f64);
+    }
+
+    pub fn clock_disable(&mut self, model: &mut Model, timestamp: u64, e: ClockDisableFtraceEvent) {
+        model.counter(format!("power.clock.{}.enabled", e.name()), timestamp, e.state() as f64);
+    }
+
+    pub fn clock_set_rate(&mut self, model: &mut Model, timestamp: u64, e: ClockSetRateFtraceEvent) {
+        model.counter(format!("power.clock.{}.rate", e.name()), timestamp, e.state() as f64);
+    }
+}
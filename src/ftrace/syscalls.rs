@@ -0,0 +1,67 @@
+//! raw_syscalls sys_enter/sys_exit paired into per thread syscall slices, with
+//! counts and latencies per syscall for the `syscalls` view.
+
+use std::collections::{BTreeMap, HashMap};
+
+use super::syscall_table;
+use crate::histogram::{format_duration, Histogram};
+use crate::model::Model;
+use crate::perfetto::{SysEnterFtraceEvent, SysExitFtraceEvent};
+
+#[derive(Default)]
+pub struct Syscalls {
+    table: Option<&'static [&'static str]>,
+    // per thread (start, syscall number, args)
+    open: HashMap<u32, (u64, i64, Vec<u64>)>,
+    latencies: BTreeMap<String, Histogram>,
+}
+
+impl Syscalls {
+    /// Picks the syscall table from `uname -m` of the traced machine.
+    pub fn set_machine(&mut self, machine: &str) {
+        self.table = match machine {
+            "x86_64" => Some(syscall_table::X86_64),
+            "aarch64" | "arm64" => Some(syscall_table::ARM64),
+            m if m.starts_with("arm") => Some(syscall_table::ARM),
+            _ => {
+                eprintln!("no syscall table for {}, syscalls will only be numbered", machine);
+                None
+            },
+        };
+    }
+
+    fn name(&self, id: i64) -> String {
+        let name = self.table.and_then(|table| table.get(usize::try_from(id).ok()?)).filter(|name| !name.is_empty());
+        match name {
+            Some(name) => name.to_string(),
+            None => format!("syscall_{}", id),
+        }
+    }
+
+    pub fn sys_enter(&mut self, timestamp: u64, tid: u32, e: SysEnterFtraceEvent) {
+        self.open.insert(tid, (timestamp, e.id(), e.args));
+    }
+
+    pub fn sys_exit(&mut self, model: &mut Model, timestamp: u64, tid: u32, e: SysExitFtraceEvent) {
+        match self.open.remove(&tid) {
+            Some((start, id, args)) if id == e.id() => {
+                let name = self.name(id);
+                let args: Vec<_> = args.iter().map(|a| format!("{:#x}", a)).collect();
+                model.slice(tid, "syscall", start, timestamp, &format!("{}({}) = {}", name, args.join(", "), e.ret()));
+                self.latencies.entry(name).or_default().add(timestamp.saturating_sub(start));
+            },
+            // exits without an enter happen at the start of the trace, and for
+            // syscalls like exit that don't return
+            _ => (),
+        }
+    }
+
+    pub fn print_summary(&self) {
+        let mut syscalls: Vec<_> = self.latencies.iter().collect();
+        syscalls.sort_by_key(|(_, h)| std::cmp::Reverse(h.total));
+        println!("{:<24} {:>10} {:>10} {:>10} {:>10}", "syscall", "count", "total", "mean", "max");
+        for (name, h) in syscalls {
+            println!("{:<24} {:>10} {:>10} {:>10} {:>10}", name, h.count, format_duration(h.total), format_duration(h.mean()), format_duration(h.max));
+        }
+    }
+}
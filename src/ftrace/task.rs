@@ -0,0 +1,26 @@
+//! Keeps the thread and process names in `Model::threads` up to date from
+//! task lifecycle events and the comms in sched events.
+
+use crate::model::Model;
+use crate::perfetto::ftrace_event::Event;
+use crate::perfetto::FtraceEvent;
+
+/// Runs before anything else looks at the event, so its output already uses
+/// the names it sets.
+pub fn names(model: &mut Model, timestamp: u64, pid: u32, e: &FtraceEvent) {
+    let threads = &mut model.threads;
+    match &e.event {
+        Some(Event::TaskNewtask(e)) => threads.new_task(e.pid() as u32, timestamp, pid, e.comm(), e.clone_flags()),
+        Some(Event::TaskRename(e)) => threads.comm_seen(e.pid() as u32, timestamp, e.newcomm()),
+        Some(Event::SchedProcessExec(e)) => threads.exec(e.pid() as u32, timestamp, e.filename()),
+        Some(Event::SchedSwitch(e)) => {
+            threads.comm_seen(e.prev_pid() as u32, timestamp, e.prev_comm());
+            threads.comm_seen(e.next_pid() as u32, timestamp, e.next_comm());
+        },
+        Some(Event::SchedWakeup(e)) => threads.comm_seen(e.pid() as u32, timestamp, e.comm()),
+        Some(Event::SchedWaking(e)) => threads.comm_seen(e.pid() as u32, timestamp, e.comm()),
+        Some(Event::SchedWakeupNew(e)) => threads.comm_seen(e.pid() as u32, timestamp, e.comm()),
+        Some(Event::OomScoreAdjUpdate(e)) => threads.comm_seen(e.pid() as u32, timestamp, e.comm()),
+        _ => (),
+    }
+}
@@ -0,0 +1,84 @@
+//! Textual rendering of any ftrace event in the format of the kernel's
+//! trace_pipe, e.g.
+//!
+//! `          <...>-1356    [002] ....   123.456789: cpu_frequency: state=1800000 cpu_id=2`
+//!
+//! prost doesn't give us reflection, so the names and fields of the typed
+//! events come from the table in event_table.rs. `GenericFtraceEvent`s, used
+//! for tracepoints without a typed message, carry their own field names. The
+//! `FtraceDescriptor` in these protos only lists atrace categories, so it has
+//! nothing to add here.
+
+use std::fmt::Write;
+
+use super::event_table;
+use crate::perfetto::{ftrace_event::Event, generic_ftrace_event::field::Value, FtraceEvent};
+
+/// The name of the event and its fields, with unset fields left out.
+pub fn fields(event: &Event) -> (String, Vec<(String, String)>) {
+    if let Event::Generic(generic) = event {
+        let fields = generic.field.iter().map(|field| {
+            let value = match &field.value {
+                Some(Value::StrValue(s)) => s.clone(),
+                Some(Value::IntValue(i)) => i.to_string(),
+                Some(Value::UintValue(u)) => u.to_string(),
+                None => String::new(),
+            };
+            (field.name().to_owned(), value)
+        }).collect();
+        return (generic.event_name().to_owned(), fields);
+    }
+    let (name, fields) = event_table::fields(event).unwrap_or_default();
+    (name.to_owned(), fields.into_iter().map(|(name, value)| (name.to_owned(), value)).collect())
+}
+
+/// sched_switch's prev_state the way the kernel prints it, "S", "D", "R+"...
+pub fn task_state(state: i64) -> String {
+    // TASK_REPORT in include/linux/sched.h, TASK_REPORT_MAX marks preemption
+    const STATES: &[u8] = b"SDTtXZPI";
+    let mut s = String::new();
+    for (bit, &c) in STATES.iter().enumerate() {
+        if state & (1 << bit) != 0 {
+            s.push(c as char);
+        }
+    }
+    if s.is_empty() {
+        s.push('R');
+    }
+    if state & (1 << STATES.len()) != 0 {
+        s.push('+');
+    }
+    s
+}
+
+/// One trace_pipe line for `e`. Timestamps are left in the ftrace clock, like
+/// trace_pipe does.
+pub fn trace_pipe_line(cpu: u32, comm: &str, e: &FtraceEvent) -> String {
+    let timestamp = e.timestamp();
+    let mut line = format!("{:>16}-{:<7} [{:03}] .... {:>5}.{:06}: ", comm, e.pid(), cpu, timestamp / 1_000_000_000, timestamp % 1_000_000_000 / 1_000);
+    let Some(event) = &e.event else {
+        return line;
+    };
+    match event {
+        // what writes to trace_marker look like
+        Event::Print(print) => {
+            let buf = print.buf();
+            let _ = write!(line, "tracing_mark_write: {}", buf.strip_suffix('\n').unwrap_or(buf));
+        },
+        Event::SchedSwitch(e) => {
+            let _ = write!(
+                line,
+                "sched_switch: prev_comm={} prev_pid={} prev_prio={} prev_state={} ==> next_comm={} next_pid={} next_prio={}",
+                e.prev_comm(), e.prev_pid(), e.prev_prio(), task_state(e.prev_state()), e.next_comm(), e.next_pid(), e.next_prio()
+            );
+        },
+        _ => {
+            let (name, fields) = fields(event);
+            let _ = write!(line, "{}:", name);
+            for (name, value) in fields {
+                let _ = write!(line, " {}={}", name, value);
+            }
+        },
+    }
+    line
+}
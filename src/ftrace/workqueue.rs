@@ -0,0 +1,60 @@
+//! Kernel work items: a slice on the kworker for every executed work, named by
+//! the work function, with how long it sat in the queue before it ran.
+
+use std::collections::{BTreeMap, HashMap};
+
+use crate::histogram::{format_duration, Histogram};
+use crate::model::Model;
+use crate::perfetto::{WorkqueueActivateWorkFtraceEvent, WorkqueueExecuteEndFtraceEvent, WorkqueueExecuteStartFtraceEvent, WorkqueueQueueWorkFtraceEvent};
+
+#[derive(Default)]
+pub struct Workqueue {
+    // when each work struct was queued and activated
+    queued: HashMap<u64, (u64, Option<u64>)>,
+    // per kworker stack of (start, work, name)
+    running: HashMap<u32, Vec<(u64, u64, String)>>,
+    // queue to execute latency per work function
+    latencies: BTreeMap<String, Histogram>,
+}
+
+impl Workqueue {
+    pub fn queue_work(&mut self, timestamp: u64, e: WorkqueueQueueWorkFtraceEvent) {
+        self.queued.insert(e.work(), (timestamp, None));
+    }
+
+    pub fn activate_work(&mut self, timestamp: u64, e: WorkqueueActivateWorkFtraceEvent) {
+        if let Some((_, activated)) = self.queued.get_mut(&e.work()) {
+            *activated = Some(timestamp);
+        }
+    }
+
+    pub fn execute_start(&mut self, timestamp: u64, tid: u32, function: String, e: WorkqueueExecuteStartFtraceEvent) {
+        let mut name = function.clone();
+        if let Some((queued, activated)) = self.queued.remove(&e.work()) {
+            let latency = timestamp.saturating_sub(queued);
+            name += &format!(" queued={}", format_duration(latency));
+            if let Some(activated) = activated {
+                name += &format!(" activated={}", format_duration(timestamp.saturating_sub(activated)));
+            }
+            self.latencies.entry(function).or_default().add(latency);
+        }
+        self.running.entry(tid).or_default().push((timestamp, e.work(), name));
+    }
+
+    pub fn execute_end(&mut self, model: &mut Model, timestamp: u64, tid: u32, e: WorkqueueExecuteEndFtraceEvent) {
+        let stack = self.running.entry(tid).or_default();
+        // the work struct can be freed by the function, so don't insist on it matching
+        if let Some((start, work, name)) = stack.pop() {
+            if work != e.work() {
+                eprintln!("workqueue_execute_end for {:#x} while running {:#x} on {}", e.work(), work, tid);
+            }
+            model.slice(tid, "workqueue", start, timestamp, &name);
+        }
+    }
+
+    pub fn print_latencies(&self) {
+        for (function, histogram) in &self.latencies {
+            print!("{} {}", function, histogram);
+        }
+    }
+}
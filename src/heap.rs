@@ -0,0 +1,253 @@
+//! heapprofd heap dumps as call trees. Every `ProcessHeapSamples` is one dump
+//! of one heap of one process, with what was allocated and freed so far per
+//! callstack. Those become a tree of unreleased and total allocated bytes per
+//! frame, and two dumps of the same heap can be diffed to see what grew.
+//! A dump can be split over several packets, it's only resolved once the last
+//! one (the one that isn't `continued`) is in. Frames are named when the tree
+//! is built for printing, since their symbols can come later in the trace.
+//!
+//! The `StreamingAllocation`/`StreamingFree` test mode has no callstacks, it
+//! only gives live and allocated byte counters per sequence.
+
+use std::collections::{BTreeMap, HashMap};
+
+use crate::model::Model;
+use crate::perfetto::{profile_packet::ProcessHeapSamples, ProfilePacket, StreamingAllocation, StreamingFree};
+use crate::profile::{Interned, StackFrame};
+use crate::symbols::Symbols;
+
+/// Bytes per frame, `self_` for samples whose leaf is this frame and `total`
+/// including everything called from it. Signed so diffs fit too.
+#[derive(Clone, Default, PartialEq)]
+pub struct CallTree {
+    pub self_unreleased: i64,
+    pub self_allocated: i64,
+    pub total_unreleased: i64,
+    pub total_allocated: i64,
+    pub children: BTreeMap<String, CallTree>,
+}
+
+impl CallTree {
+    fn add(&mut self, frames: &[String], unreleased: i64, allocated: i64) {
+        self.total_unreleased += unreleased;
+        self.total_allocated += allocated;
+        match frames.split_first() {
+            Some((frame, rest)) => self.children.entry(frame.clone()).or_default().add(rest, unreleased, allocated),
+            None => {
+                self.self_unreleased += unreleased;
+                self.self_allocated += allocated;
+            },
+        }
+    }
+
+    /// `after - before` for every frame in either, frames that didn't change
+    /// are left out.
+    pub fn diff(before: &CallTree, after: &CallTree) -> CallTree {
+        let empty = CallTree::default();
+        let mut children = BTreeMap::new();
+        for name in before.children.keys().chain(after.children.keys()) {
+            if children.contains_key(name) {
+                continue;
+            }
+            let child = CallTree::diff(before.children.get(name).unwrap_or(&empty), after.children.get(name).unwrap_or(&empty));
+            if child != empty {
+                children.insert(name.clone(), child);
+            }
+        }
+        CallTree {
+            self_unreleased: after.self_unreleased - before.self_unreleased,
+            self_allocated: after.self_allocated - before.self_allocated,
+            total_unreleased: after.total_unreleased - before.total_unreleased,
+            total_allocated: after.total_allocated - before.total_allocated,
+            children,
+        }
+    }
+
+    /// Indented, biggest unreleased first.
+    pub fn print(&self, depth: usize) {
+        let mut children: Vec<_> = self.children.iter().collect();
+        children.sort_by_key(|(_, child)| std::cmp::Reverse(child.total_unreleased.abs()));
+        for (name, child) in children {
+            println!("{:>12} {:>12}  {:indent$}{}", child.total_unreleased, child.total_allocated, "", name, indent = depth * 2);
+            child.print(depth + 1);
+        }
+    }
+}
+
+pub struct HeapDump {
+    pub pid: u64,
+    pub heap: String,
+    // boot clock
+    pub timestamp: u64,
+    // (callstack, unreleased, allocated)
+    pub samples: Vec<(Vec<StackFrame>, i64, i64)>,
+    // sampling was cut short one way or another
+    pub incomplete: bool,
+    // from heapprofd's own stats, stacks it couldn't unwind are cut short
+    pub heap_samples: u64,
+    pub unwinding_errors: u64,
+}
+
+impl HeapDump {
+    pub fn tree(&self, symbols: &Symbols) -> CallTree {
+        let mut tree = CallTree::default();
+        for (frames, unreleased, allocated) in &self.samples {
+            tree.add(&symbols.stack_names(frames), *unreleased, *allocated);
+        }
+        tree
+    }
+}
+
+#[derive(Default)]
+struct Sequence {
+    interned: Interned,
+    // dumps waiting for the rest of their packets
+    pending: Vec<ProcessHeapSamples>,
+    // streaming mode: live allocations by (heap, address)
+    live: HashMap<(u32, u64), u64>,
+    live_bytes: u64,
+    allocated_bytes: u64,
+}
+
+impl Sequence {
+    fn counters(&self, model: &mut Model, timestamp: u64, sequence_id: u32) {
+        model.counter(format!("mem.heap.{}.live", sequence_id), timestamp, self.live_bytes as f64);
+        model.counter(format!("mem.heap.{}.allocated", sequence_id), timestamp, self.allocated_bytes as f64);
+    }
+}
+
+#[derive(Default)]
+pub struct Heaps {
+    sequences: HashMap<u32, Sequence>,
+    pub dumps: Vec<HeapDump>,
+}
+
+impl Heaps {
+    pub fn interned(&self, sequence_id: u32) -> Option<&Interned> {
+        self.sequences.get(&sequence_id).map(|sequence| &sequence.interned)
+    }
+
+    pub fn profile_packet(&mut self, sequence_id: u32, packet: ProfilePacket) {
+        let sequence = self.sequences.entry(sequence_id).or_default();
+        sequence.interned.add_profile(&packet.strings, &packet.mappings, &packet.frames, &packet.callstacks);
+        let continued = packet.continued();
+        sequence.pending.extend(packet.process_dumps);
+        if continued {
+            return;
+        }
+        for samples in std::mem::take(&mut sequence.pending) {
+            let heap = samples.heap_name.clone().unwrap_or_else(|| "malloc".to_owned());
+            let incomplete = samples.buffer_overran() || samples.buffer_corrupted() || samples.hit_guardrail() || samples.disconnected() || samples.rejected_concurrent();
+            // the same dump continued from an earlier packet
+            let existing = self.dumps.iter().position(|d| d.pid == samples.pid() && d.heap == heap && d.timestamp == samples.timestamp());
+            let dump = match existing {
+                Some(i) => &mut self.dumps[i],
+                None => {
+                    let timestamp = samples.timestamp();
+                    self.dumps.push(HeapDump { pid: samples.pid(), heap, timestamp, samples: Vec::new(), incomplete: false, heap_samples: 0, unwinding_errors: 0 });
+                    self.dumps.last_mut().unwrap()
+                },
+            };
+            dump.incomplete |= incomplete;
+            if let Some(stats) = &samples.stats {
+                dump.heap_samples = dump.heap_samples.max(stats.heap_samples());
+                dump.unwinding_errors = dump.unwinding_errors.max(stats.unwinding_errors());
+            }
+            for sample in &samples.samples {
+                let callstack = sample.callstack_id();
+                let frames = sequence.interned.callstack_frames(sequence_id, callstack).unwrap_or_else(|| vec![StackFrame::Named(format!("callstack{}", callstack))]);
+                let unreleased = sample.self_allocated() as i64 - sample.self_freed() as i64;
+                dump.samples.push((frames, unreleased, sample.self_allocated() as i64));
+            }
+        }
+    }
+
+    /// Live and allocated bytes go to `mem.heap.{sequence}.live`/`.allocated`.
+    pub fn streaming_allocation(&mut self, model: &mut Model, timestamp: u64, sequence_id: u32, e: &StreamingAllocation) {
+        let sequence = self.sequences.entry(sequence_id).or_default();
+        for (i, (&address, &size)) in e.address.iter().zip(&e.size).enumerate() {
+            let heap = e.heap_id.get(i).copied().unwrap_or(0);
+            if let Some(old) = sequence.live.insert((heap, address), size) {
+                sequence.live_bytes -= old;
+            }
+            sequence.live_bytes += size;
+            sequence.allocated_bytes += size;
+        }
+        sequence.counters(model, timestamp, sequence_id);
+    }
+
+    pub fn streaming_free(&mut self, model: &mut Model, timestamp: u64, sequence_id: u32, e: &StreamingFree) {
+        let sequence = self.sequences.entry(sequence_id).or_default();
+        for (i, &address) in e.address.iter().enumerate() {
+            let heap = e.heap_id.get(i).copied().unwrap_or(0);
+            if let Some(size) = sequence.live.remove(&(heap, address)) {
+                sequence.live_bytes -= size;
+            }
+        }
+        sequence.counters(model, timestamp, sequence_id);
+    }
+
+    /// Every dump as a tree, then for heaps dumped more than once what
+    /// changed between the first and the last dump.
+    pub fn print(&self, model: &Model, symbols: &Symbols) {
+        let process_name = |pid: u64, timestamp| model.threads.process_name(pid as u32, timestamp).unwrap_or("<...>").replace(' ', "_");
+        println!("{:>12} {:>12}  frame", "unreleased", "allocated");
+        let mut by_heap: BTreeMap<(u64, &str), Vec<(&HeapDump, CallTree)>> = BTreeMap::new();
+        for dump in &self.dumps {
+            let tree = dump.tree(symbols);
+            let incomplete = if dump.incomplete { " INCOMPLETE" } else { "" };
+            let at = model.to_mono(dump.timestamp);
+            println!("{}({}) {} dump at {}, {} samples, {} unwinding errors{}", process_name(dump.pid, dump.timestamp), dump.pid, dump.heap, at, dump.heap_samples, dump.unwinding_errors, incomplete);
+            tree.print(1);
+            by_heap.entry((dump.pid, &dump.heap)).or_default().push((dump, tree));
+        }
+        for ((pid, heap), dumps) in by_heap {
+            if let [(first, first_tree), .., (last, last_tree)] = dumps.as_slice() {
+                println!("{}({}) {} from {} to {}", process_name(pid, last.timestamp), pid, heap, model.to_mono(first.timestamp), model.to_mono(last.timestamp));
+                CallTree::diff(first_tree, last_tree).print(1);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn tree(samples: &[(&[&str], i64, i64)]) -> CallTree {
+        let mut tree = CallTree::default();
+        for (frames, unreleased, allocated) in samples {
+            let frames: Vec<String> = frames.iter().map(|f| f.to_string()).collect();
+            tree.add(&frames, *unreleased, *allocated);
+        }
+        tree
+    }
+
+    #[test]
+    fn add_sums_up_the_stack() {
+        let tree = tree(&[(&["main", "a"], 10, 20), (&["main", "b"], 5, 5), (&["main"], 1, 1)]);
+        assert_eq!((tree.total_unreleased, tree.total_allocated), (16, 26));
+        let main = &tree.children["main"];
+        assert_eq!((main.self_unreleased, main.total_unreleased), (1, 16));
+        assert_eq!((main.children["a"].total_unreleased, main.children["a"].total_allocated), (10, 20));
+    }
+
+    #[test]
+    fn diff_leaves_out_what_didnt_change() {
+        let before = tree(&[(&["main", "a"], 10, 20), (&["main", "b"], 5, 5), (&["main", "gone"], 3, 3)]);
+        let after = tree(&[(&["main", "a"], 15, 30), (&["main", "b"], 5, 5), (&["main", "new"], 7, 7)]);
+        let diff = CallTree::diff(&before, &after);
+        assert_eq!((diff.total_unreleased, diff.total_allocated), (9, 14));
+        let main = &diff.children["main"];
+        assert_eq!(main.children.keys().collect::<Vec<_>>(), ["a", "gone", "new"]);
+        assert_eq!((main.children["a"].total_unreleased, main.children["a"].self_allocated), (5, 10));
+        assert_eq!(main.children["gone"].total_unreleased, -3);
+        assert_eq!(main.children["new"].total_allocated, 7);
+    }
+
+    #[test]
+    fn diff_of_the_same_tree_is_empty() {
+        let before = tree(&[(&["main", "a"], 10, 20)]);
+        assert!(CallTree::diff(&before, &before) == CallTree::default());
+    }
+}
@@ -0,0 +1,421 @@
+//! Java heap dumps from `HeapGraph` packets. A dump is spread over as many
+//! packets as it takes, the last one isn't `continued`, and ids of types and
+//! field names hold for the whole dump. Everything reachable from the roots is
+//! live, the rest is garbage that wasn't collected yet.
+//!
+//! Retained sizes come from the dominator tree over the live objects: an
+//! object retains everything that's only reachable through it, which is what
+//! would be freed if it went away. Class and field names can be obfuscated by
+//! ProGuard/R8, `DeobfuscationMapping` packets map them back.
+
+use std::collections::{BTreeMap, HashMap, VecDeque};
+
+use crate::model::Model;
+use crate::perfetto::{heap_graph_object::Identifier, DeobfuscationMapping, HeapGraph};
+
+fn string(bytes: &Option<Vec<u8>>) -> String {
+    String::from_utf8_lossy(bytes.as_deref().unwrap_or_default()).into_owned()
+}
+
+pub struct Object {
+    pub type_id: u64,
+    pub self_size: u64,
+    // (field name id, object id), the field is 0 when it comes from the
+    // type and the object is 0 for null
+    pub references: Vec<(u64, u64)>,
+}
+
+pub struct Type {
+    pub name: String,
+    pub superclass_id: Option<u64>,
+    // field name ids of the references of instances, superclass not included
+    pub reference_field_ids: Vec<u64>,
+}
+
+#[derive(Default)]
+pub struct HeapGraphDump {
+    pub pid: i32,
+    // boot clock
+    pub timestamp: u64,
+    pub objects: HashMap<u64, Object>,
+    pub types: HashMap<u64, Type>,
+    pub field_names: HashMap<u64, String>,
+    // (object id, root type)
+    pub roots: Vec<(u64, String)>,
+    // packets seen, and whether any went missing going by their index
+    packets: u64,
+    pub incomplete: bool,
+}
+
+/// The dominator tree of the live objects.
+pub struct Dominators {
+    // object id -> its immediate dominator, None for the ones only dominated
+    // by the roots as a whole
+    pub idom: HashMap<u64, Option<u64>>,
+    pub retained: HashMap<u64, u64>,
+}
+
+impl HeapGraphDump {
+    fn add(&mut self, graph: HeapGraph) {
+        if graph.index() != self.packets {
+            self.incomplete = true;
+        }
+        self.packets = graph.index() + 1;
+        for t in graph.types {
+            let (id, superclass_id) = (t.id(), t.superclass_id.filter(|id| *id != 0));
+            let reference_field_ids = t.reference_field_id;
+            self.types.insert(id, Type { name: t.class_name.unwrap_or_default(), superclass_id, reference_field_ids });
+        }
+        for name in &graph.field_names {
+            self.field_names.insert(name.iid(), string(&name.str));
+        }
+        // ids are either absolute or relative to the previous object
+        let mut previous_id = 0;
+        for o in graph.objects {
+            let id = match o.identifier {
+                Some(Identifier::Id(id)) => id,
+                Some(Identifier::IdDelta(delta)) => previous_id + delta,
+                None => continue,
+            };
+            previous_id = id;
+            let base = o.reference_field_id_base();
+            // 0 is a null reference, kept so fields from the type line up
+            let references = o
+                .reference_object_id
+                .iter()
+                .enumerate()
+                .map(|(i, object)| (o.reference_field_id.get(i).copied().unwrap_or(0), if *object == 0 { 0 } else { object + base }))
+                .collect();
+            self.objects.insert(id, Object { type_id: o.type_id(), self_size: o.self_size(), references });
+        }
+        for root in graph.roots {
+            let root_type = root.root_type().as_str_name();
+            self.roots.extend(root.object_ids.iter().map(|id| (*id, root_type.to_owned())));
+        }
+    }
+
+    pub fn type_name(&self, object: &Object) -> &str {
+        self.types.get(&object.type_id).map_or("<unknown>", |t| t.name.as_str())
+    }
+
+    /// The field an object refers to another through. Since Android S the
+    /// field names of plain instances are only on the type, in the order of
+    /// the class's own fields, then its superclass's and so on.
+    pub fn field_name(&self, object: &Object, i: usize) -> Option<&str> {
+        let mut id = object.references[i].0;
+        if id == 0 {
+            let mut fields = Vec::new();
+            let mut type_id = Some(object.type_id);
+            while let Some(t) = type_id.and_then(|id| self.types.get(&id)) {
+                fields.extend(&t.reference_field_ids);
+                type_id = t.superclass_id;
+            }
+            id = *fields.get(i)?;
+        }
+        self.field_names.get(&id).map(String::as_str)
+    }
+
+    /// Dominators with Cooper, Harvey and Kennedy's iterative algorithm, with
+    /// a made up node above all the roots.
+    pub fn dominators(&self) -> Dominators {
+        // depth first from the roots, to number the live objects in postorder
+        let mut number = HashMap::new();
+        let mut postorder: Vec<u64> = Vec::new();
+        let mut stack: Vec<(u64, usize)> = Vec::new();
+        for (root, _) in &self.roots {
+            if !self.objects.contains_key(root) || number.contains_key(root) {
+                continue;
+            }
+            number.insert(*root, usize::MAX);
+            stack.push((*root, 0));
+            while let Some((id, next)) = stack.last_mut() {
+                let references = &self.objects[id].references;
+                match references.get(*next) {
+                    Some((_, to)) => {
+                        *next += 1;
+                        if self.objects.contains_key(to) && !number.contains_key(to) {
+                            number.insert(*to, usize::MAX);
+                            stack.push((*to, 0));
+                        }
+                    },
+                    None => {
+                        number.insert(*id, postorder.len());
+                        postorder.push(*id);
+                        stack.pop();
+                    },
+                }
+            }
+        }
+
+        // the made up root is numbered after everything else
+        let top = postorder.len();
+        let mut predecessors = vec![Vec::new(); top];
+        for (id, n) in &number {
+            for (_, to) in &self.objects[id].references {
+                if let Some(to) = number.get(to) {
+                    predecessors[*to].push(*n);
+                }
+            }
+        }
+        for (root, _) in &self.roots {
+            if let Some(n) = number.get(root) {
+                predecessors[*n].push(top);
+            }
+        }
+
+        let mut idom = vec![None; top + 1];
+        idom[top] = Some(top);
+        let intersect = |idom: &[Option<usize>], mut a: usize, mut b: usize| {
+            while a != b {
+                while a < b {
+                    a = idom[a].unwrap();
+                }
+                while b < a {
+                    b = idom[b].unwrap();
+                }
+            }
+            a
+        };
+        let mut changed = true;
+        while changed {
+            changed = false;
+            for n in (0..top).rev() {
+                let mut processed = predecessors[n].iter().copied().filter(|p| idom[*p].is_some());
+                let Some(first) = processed.next() else {
+                    continue;
+                };
+                let new = processed.fold(first, |new, p| intersect(&idom, p, new));
+                if idom[n] != Some(new) {
+                    idom[n] = Some(new);
+                    changed = true;
+                }
+            }
+        }
+
+        // a dominator comes later in postorder than what it dominates
+        let mut retained: Vec<u64> = postorder.iter().map(|id| self.objects[id].self_size).collect();
+        retained.push(0);
+        for n in 0..top {
+            let parent = idom[n].unwrap();
+            retained[parent] += retained[n];
+        }
+        Dominators {
+            idom: postorder.iter().enumerate().map(|(n, id)| (*id, idom[n].filter(|p| *p != top).map(|p| postorder[p]))).collect(),
+            retained: postorder.iter().enumerate().map(|(n, id)| (*id, retained[n])).collect(),
+        }
+    }
+
+    /// Live objects and bytes, largest retained first, then the objects
+    /// retaining the most with the path from their root.
+    pub fn print(&self, model: &Model, deobfuscation: &Deobfuscation) {
+        let dominators = self.dominators();
+        let live_bytes: u64 = dominators.idom.keys().map(|id| self.objects[id].self_size).sum();
+        let total_bytes: u64 = self.objects.values().map(|o| o.self_size).sum();
+        let incomplete = if self.incomplete { " INCOMPLETE" } else { "" };
+        let process_name = model.threads.process_name(self.pid as u32, self.timestamp).unwrap_or("<...>");
+        let deobfuscation = deobfuscation.package(process_name);
+        let process_name = process_name.replace(' ', "_");
+        let (live, total) = (dominators.idom.len(), self.objects.len());
+        println!("{}({}) java heap at {}: {} of {} objects live, {} of {} bytes{}", process_name, self.pid, model.to_mono(self.timestamp), live, total, live_bytes, total_bytes, incomplete);
+
+        // per class, not counting what's retained by another object of the
+        // same class further up the dominator tree, so that nested lists
+        // aren't counted over and over
+        let mut children: HashMap<Option<u64>, Vec<u64>> = HashMap::new();
+        for (id, parent) in &dominators.idom {
+            children.entry(*parent).or_default().push(*id);
+        }
+        let mut classes: BTreeMap<String, (u64, u64, u64)> = BTreeMap::new();
+        // classes on the path down from the roots, by how often
+        let mut path: HashMap<String, usize> = HashMap::new();
+        let mut stack: Vec<(u64, bool)> = children.get(&None).into_iter().flatten().map(|id| (*id, false)).collect();
+        while let Some((id, done)) = stack.pop() {
+            let object = &self.objects[&id];
+            let class = deobfuscation.class(self.type_name(object));
+            if done {
+                *path.get_mut(&class).unwrap() -= 1;
+                continue;
+            }
+            let entry = classes.entry(class.clone()).or_default();
+            entry.0 += 1;
+            entry.1 += object.self_size;
+            let above = path.entry(class).or_default();
+            if *above == 0 {
+                entry.2 += dominators.retained[&id];
+            }
+            *above += 1;
+            stack.push((id, true));
+            stack.extend(children.get(&Some(id)).into_iter().flatten().map(|child| (*child, false)));
+        }
+        let mut classes: Vec<_> = classes.into_iter().collect();
+        classes.sort_by_key(|(_, (_, _, retained))| std::cmp::Reverse(*retained));
+        println!("{:>10} {:>12} {:>12}  class", "count", "self", "retained");
+        for (class, (count, self_size, retained)) in classes.iter().take(30) {
+            println!("{:>10} {:>12} {:>12}  {}", count, self_size, retained, class);
+        }
+
+        // paths to the biggest retainers, the shortest one breadth first from
+        // the roots in the order they were dumped, so it's the same every run
+        let roots: HashMap<u64, &str> = self.roots.iter().rev().map(|(id, root_type)| (*id, root_type.as_str())).collect();
+        let mut parents = HashMap::new();
+        let mut queue: VecDeque<u64> = self.roots.iter().map(|(id, _)| *id).filter(|id| self.objects.contains_key(id)).collect();
+        while let Some(id) = queue.pop_front() {
+            for (i, (_, to)) in self.objects[&id].references.iter().enumerate() {
+                if *to != 0 && !roots.contains_key(to) && !parents.contains_key(to) && self.objects.contains_key(to) {
+                    parents.insert(*to, (id, i));
+                    queue.push_back(*to);
+                }
+            }
+        }
+        let mut biggest: Vec<_> = dominators.retained.iter().collect();
+        biggest.sort_by_key(|(id, retained)| (std::cmp::Reverse(**retained), **id));
+        println!("{:>12}  object", "retained");
+        for (id, retained) in biggest.into_iter().take(10) {
+            let object = &self.objects[id];
+            println!("{:>12}  {}@{:#x}", retained, deobfuscation.class(self.type_name(object)), id);
+            let mut current = *id;
+            for _ in 0..20 {
+                if let Some(root_type) = roots.get(&current) {
+                    println!("{:>12}    {}", "", root_type);
+                    break;
+                }
+                let Some((parent, i)) = parents.get(&current) else {
+                    break;
+                };
+                let parent_object = &self.objects[parent];
+                let class = self.type_name(parent_object);
+                let field = self.field_name(parent_object, *i).map(|f| deobfuscation.field(f)).unwrap_or_else(|| format!("[{}]", i));
+                println!("{:>12}    from {}@{:#x} {}", "", deobfuscation.class(class), parent, field);
+                current = *parent;
+            }
+        }
+    }
+}
+
+/// Obfuscated class names to real ones, and the same for members per class,
+/// per package since every app is obfuscated on its own.
+#[derive(Default)]
+pub struct Deobfuscation {
+    packages: HashMap<String, Classes>,
+    // for processes without any
+    none: Classes,
+}
+
+impl Deobfuscation {
+    pub fn add(&mut self, mapping: &DeobfuscationMapping) {
+        let classes = &mut self.packages.entry(mapping.package_name().to_owned()).or_default().classes;
+        for class in &mapping.obfuscated_classes {
+            let members = class.obfuscated_members.iter().map(|m| (m.obfuscated_name().to_owned(), m.deobfuscated_name().to_owned())).collect();
+            classes.insert(class.obfuscated_name().to_owned(), (class.deobfuscated_name().to_owned(), members));
+        }
+    }
+
+    /// The mappings for a process, by its name without the ":service" part,
+    /// or the ones that didn't say which package they're for.
+    pub fn package(&self, process_name: &str) -> &Classes {
+        let package = process_name.split_once(':').map_or(process_name, |(package, _)| package);
+        self.packages.get(package).or_else(|| self.packages.get("")).unwrap_or(&self.none)
+    }
+}
+
+/// One package's mappings.
+#[derive(Default)]
+pub struct Classes {
+    classes: HashMap<String, (String, HashMap<String, String>)>,
+}
+
+impl Classes {
+    /// Array types are deobfuscated by their element type.
+    pub fn class(&self, name: &str) -> String {
+        let element = name.trim_end_matches("[]");
+        match self.classes.get(element) {
+            Some((real, _)) => format!("{}{}", real, &name[element.len()..]),
+            None => name.to_owned(),
+        }
+    }
+
+    /// Field names are "type class.field".
+    pub fn field(&self, name: &str) -> String {
+        let Some((field_type, qualified)) = name.split_once(' ') else {
+            return name.to_owned();
+        };
+        let Some((class, field)) = qualified.rsplit_once('.') else {
+            return name.to_owned();
+        };
+        let field = match self.classes.get(class).and_then(|(_, members)| members.get(field)) {
+            // fully qualified when it moved to another class
+            Some(real) if real.contains('.') => return format!("{} {}", self.class(field_type), real),
+            Some(real) => real.as_str(),
+            None => field,
+        };
+        format!("{} {}.{}", self.class(field_type), self.class(class), field)
+    }
+}
+
+#[derive(Default)]
+pub struct HeapGraphs {
+    // dumps still getting packets, per sequence
+    pending: HashMap<u32, HeapGraphDump>,
+    pub dumps: Vec<HeapGraphDump>,
+    pub deobfuscation: Deobfuscation,
+}
+
+impl HeapGraphs {
+    pub fn heap_graph(&mut self, sequence_id: u32, timestamp: u64, graph: HeapGraph) {
+        let continued = graph.continued();
+        let dump = self.pending.entry(sequence_id).or_insert_with(|| HeapGraphDump { pid: graph.pid(), timestamp, ..Default::default() });
+        dump.add(graph);
+        if !continued {
+            self.dumps.push(self.pending.remove(&sequence_id).unwrap());
+        }
+    }
+
+    /// Dumps that never got their last packet are printed too, marked
+    /// incomplete.
+    pub fn finish(&mut self) {
+        for (_, mut dump) in self.pending.drain() {
+            dump.incomplete = true;
+            self.dumps.push(dump);
+        }
+        self.dumps.sort_by_key(|d| (d.timestamp, d.pid));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // objects are (id, self size, references), sizes are powers of two so
+    // every retained size says which objects went into it
+    fn dump(objects: &[(u64, u64, &[u64])], roots: &[u64]) -> HeapGraphDump {
+        let mut dump = HeapGraphDump::default();
+        for (id, self_size, references) in objects {
+            let references = references.iter().map(|to| (0, *to)).collect();
+            dump.objects.insert(*id, Object { type_id: 0, self_size: *self_size, references });
+        }
+        dump.roots = roots.iter().map(|id| (*id, "ROOT_JAVA_FRAME".to_owned())).collect();
+        dump
+    }
+
+    #[test]
+    fn diamond() {
+        // 1 -> 2 -> 4 -> 5, 1 -> 3 -> 4, 6 is garbage pointing into the graph
+        let dump = dump(&[(1, 1, &[2, 3]), (2, 2, &[4]), (3, 4, &[4]), (4, 8, &[5]), (5, 16, &[]), (6, 32, &[5])], &[1]);
+        let dominators = dump.dominators();
+        let idom: BTreeMap<_, _> = dominators.idom.into_iter().collect();
+        assert_eq!(idom, BTreeMap::from([(1, None), (2, Some(1)), (3, Some(1)), (4, Some(1)), (5, Some(4))]));
+        let retained: BTreeMap<_, _> = dominators.retained.into_iter().collect();
+        assert_eq!(retained, BTreeMap::from([(1, 31), (2, 2), (3, 4), (4, 24), (5, 16)]));
+    }
+
+    #[test]
+    fn shared_between_roots() {
+        // 3 is reachable from both roots, so only the roots as a whole keep
+        // it, nulls and cycles go nowhere
+        let dump = dump(&[(1, 1, &[3, 0]), (2, 2, &[3]), (3, 4, &[4]), (4, 8, &[3])], &[1, 2]);
+        let dominators = dump.dominators();
+        assert_eq!(dominators.idom[&3], None);
+        assert_eq!(dominators.idom[&4], Some(3));
+        assert_eq!((dominators.retained[&1], dominators.retained[&2], dominators.retained[&3]), (1, 2, 12));
+    }
+}
@@ -0,0 +1,51 @@
+use std::fmt;
+
+/// Latency histogram with power of two buckets, in nanoseconds.
+#[derive(Default, Clone)]
+pub struct Histogram {
+    buckets: Vec<u64>,
+    pub count: u64,
+    pub total: u64,
+    pub max: u64,
+}
+
+impl Histogram {
+    pub fn add(&mut self, value: u64) {
+        let bucket = (u64::BITS - value.leading_zeros()) as usize;
+        if self.buckets.len() <= bucket {
+            self.buckets.resize(bucket + 1, 0);
+        }
+        self.buckets[bucket] += 1;
+        self.count += 1;
+        self.total += value;
+        self.max = self.max.max(value);
+    }
+
+    pub fn mean(&self) -> u64 {
+        self.total.checked_div(self.count).unwrap_or(0)
+    }
+}
+
+pub fn format_duration(ns: u64) -> String {
+    match ns {
+        0..=9_999 => format!("{}ns", ns),
+        10_000..=9_999_999 => format!("{}us", ns / 1_000),
+        10_000_000..=9_999_999_999 => format!("{}ms", ns / 1_000_000),
+        _ => format!("{}s", ns / 1_000_000_000),
+    }
+}
+
+impl fmt::Display for Histogram {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        writeln!(f, "count={} mean={} max={} total={}", self.count, format_duration(self.mean()), format_duration(self.max), format_duration(self.total))?;
+        let largest = self.buckets.iter().copied().max().unwrap_or(0);
+        let first = self.buckets.iter().position(|&c| c != 0).unwrap_or(0);
+        for (bucket, &count) in self.buckets.iter().enumerate().skip(first) {
+            // bucket n holds values in [2^(n-1), 2^n)
+            let low = if bucket == 0 { 0 } else { 1u64 << (bucket - 1) };
+            let bar = "#".repeat((count * 40).div_ceil(largest.max(1)) as usize);
+            writeln!(f, "  >= {:>8} {:>10} {}", format_duration(low), count, bar)?;
+        }
+        Ok(())
+    }
+}
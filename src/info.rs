@@ -0,0 +1,250 @@
+//! What a trace says about itself: the config it was recorded with, the
+//! machine it was recorded on and its uuid. These packets are written once
+//! near the start of the trace.
+
+use std::fmt::Display;
+
+use prost::DecodeError;
+
+use crate::perfetto::trace_packet::Data;
+use crate::perfetto::perf_events::{timebase::{Event, Interval}, Counter};
+use crate::perfetto::{AndroidLogId, AndroidLogPriority, CpuInfo, DataSourceConfig, SystemInfo, TraceConfig, TracePacket};
+use crate::reader::Packets;
+
+#[derive(Default)]
+pub struct TraceInfo {
+    pub config: Option<TraceConfig>,
+    pub system: Option<SystemInfo>,
+    pub cpus: Option<CpuInfo>,
+    // from the TraceUuid packet, older traces only have it in the config
+    uuid: Option<(i64, i64)>,
+}
+
+/// The usual 8-4-4-4-12 hex form of the 128 bits.
+pub fn format_uuid(msb: i64, lsb: i64) -> String {
+    let hex = format!("{:016x}{:016x}", msb as u64, lsb as u64);
+    format!("{}-{}-{}-{}-{}", &hex[..8], &hex[8..12], &hex[12..16], &hex[16..20], &hex[20..])
+}
+
+// 1800000 kHz -> 1.8GHz
+fn format_khz(khz: u32) -> String {
+    match khz {
+        0..1000 => format!("{}kHz", khz),
+        1000..1_000_000 => format!("{}MHz", khz as f64 / 1e3),
+        _ => format!("{}GHz", khz as f64 / 1e6),
+    }
+}
+
+// `name=value` of the settings that are set, lists comma separated
+#[derive(Default)]
+struct Settings(Vec<String>);
+
+impl Settings {
+    fn set(&mut self, name: &str, value: Option<impl Display>) -> &mut Self {
+        if let Some(value) = value {
+            self.0.push(format!("{}={}", name, value));
+        }
+        self
+    }
+
+    fn list(&mut self, name: &str, values: &[impl Display]) -> &mut Self {
+        if !values.is_empty() {
+            let values: Vec<_> = values.iter().map(ToString::to_string).collect();
+            self.0.push(format!("{}={}", name, values.join(",")));
+        }
+        self
+    }
+
+    fn line(&self) -> String {
+        self.0.join(" ")
+    }
+}
+
+// the settings of the data sources this tool reads something from, the
+// others only get their name
+fn source_settings(config: &DataSourceConfig) -> String {
+    let mut s = Settings::default();
+    s.set("duration_ms", config.trace_duration_ms).set("stop_timeout_ms", config.stop_timeout_ms);
+    if let Some(c) = &config.ftrace_config {
+        s.list("events", &c.ftrace_events).list("atrace_categories", &c.atrace_categories).list("atrace_apps", &c.atrace_apps);
+        s.list("syscalls", &c.syscall_events).set("function_graph", c.enable_function_graph).list("function_filters", &c.function_filters).list("function_graph_roots", &c.function_graph_roots);
+        s.set("buffer_size_kb", c.buffer_size_kb).set("drain_period_ms", c.drain_period_ms);
+        s.set("compact_sched", c.compact_sched.as_ref().and_then(|c| c.enabled)).set("symbolize_ksyms", c.symbolize_ksyms).set("instance", c.instance_name.as_ref());
+    }
+    if let Some(c) = &config.process_stats_config {
+        s.set("poll_ms", c.proc_stats_poll_ms).set("scan_all_processes_on_start", c.scan_all_processes_on_start).set("record_thread_names", c.record_thread_names);
+    }
+    if let Some(c) = &config.sys_stats_config {
+        s.set("meminfo_period_ms", c.meminfo_period_ms).set("vmstat_period_ms", c.vmstat_period_ms).set("stat_period_ms", c.stat_period_ms);
+        s.set("devfreq_period_ms", c.devfreq_period_ms).set("cpufreq_period_ms", c.cpufreq_period_ms).set("buddyinfo_period_ms", c.buddyinfo_period_ms);
+        s.set("diskstat_period_ms", c.diskstat_period_ms).set("psi_period_ms", c.psi_period_ms);
+    }
+    if let Some(c) = &config.heapprofd_config {
+        s.list("cmdline", &c.process_cmdline).list("pid", &c.pid).set("all", c.all).list("heaps", &c.heaps).set("sampling_interval_bytes", c.sampling_interval_bytes);
+        s.set("stream_allocations", c.stream_allocations).set("dump_interval_ms", c.continuous_dump_config.as_ref().and_then(|c| c.dump_interval_ms)).set("shmem_size_bytes", c.shmem_size_bytes);
+    }
+    if let Some(c) = &config.java_hprof_config {
+        s.list("cmdline", &c.process_cmdline).list("pid", &c.pid).set("dump_interval_ms", c.continuous_dump_config.as_ref().and_then(|c| c.dump_interval_ms));
+    }
+    if let Some(c) = &config.perf_event_config {
+        if let Some(timebase) = &c.timebase {
+            match timebase.interval {
+                Some(Interval::Frequency(hz)) => s.set("frequency", Some(hz)),
+                Some(Interval::Period(period)) => s.set("period", Some(period)),
+                None => &mut s,
+            };
+            match &timebase.event {
+                Some(Event::Counter(counter)) => s.set("counter", Counter::try_from(*counter).ok().map(|c| c.as_str_name())),
+                Some(Event::Tracepoint(tracepoint)) => s.set("tracepoint", tracepoint.name.as_ref()),
+                Some(Event::RawEvent(_)) => s.set("event", Some("raw")),
+                None => &mut s,
+            };
+        }
+        if let Some(callstacks) = &c.callstack_sampling {
+            if let Some(scope) = &callstacks.scope {
+                s.list("cmdline", &scope.target_cmdline).list("pid", &scope.target_pid);
+            }
+            s.set("kernel_frames", callstacks.kernel_frames);
+        }
+        s.set("read_period_ms", c.ring_buffer_read_period_ms);
+    }
+    if let Some(c) = &config.android_log_config {
+        let log_ids: Vec<_> = c.log_ids.iter().filter_map(|id| AndroidLogId::try_from(*id).ok()).map(|id| id.as_str_name()).collect();
+        s.list("log_ids", &log_ids).set("min_prio", c.min_prio.and_then(|prio| AndroidLogPriority::try_from(prio).ok()).map(|prio| prio.as_str_name())).list("tags", &c.filter_tags);
+    }
+    if let Some(c) = &config.track_event_config {
+        s.list("enabled_categories", &c.enabled_categories).list("disabled_categories", &c.disabled_categories).list("enabled_tags", &c.enabled_tags).list("disabled_tags", &c.disabled_tags);
+    }
+    if let Some(c) = &config.network_packet_trace_config {
+        s.set("poll_ms", c.poll_ms).set("aggregation_threshold", c.aggregation_threshold).set("intern_limit", c.intern_limit);
+    }
+    if let Some(c) = &config.android_power_config {
+        s.set("battery_poll_ms", c.battery_poll_ms).set("power_rails", c.collect_power_rails);
+    }
+    if let Some(c) = &config.gpu_counter_config {
+        s.set("counter_period_ns", c.counter_period_ns).list("counters", &c.counter_ids);
+    }
+    s.line()
+}
+
+// whatever of the config isn't printed on its own
+fn other_settings(config: &TraceConfig) -> String {
+    let mut s = Settings::default();
+    s.set("write_into_file", config.write_into_file).set("output_path", config.output_path.as_ref()).set("file_write_period_ms", config.file_write_period_ms);
+    s.set("max_file_size_bytes", config.max_file_size_bytes).set("flush_period_ms", config.flush_period_ms).set("deferred_start", config.deferred_start);
+    s.set("unique_session_name", config.unique_session_name.as_ref()).set("bugreport_score", config.bugreport_score);
+    s.line()
+}
+
+impl TraceInfo {
+    /// Looks through the whole trace, any packet that isn't one of these is
+    /// skipped.
+    pub fn read(trace: &[u8]) -> Result<TraceInfo, DecodeError> {
+        let mut info = TraceInfo::default();
+        for packet in Packets::new(trace) {
+            info.packet(packet?);
+        }
+        Ok(info)
+    }
+
+    pub fn packet(&mut self, packet: TracePacket) {
+        match packet.data {
+            Some(Data::TraceConfig(config)) => self.config = Some(config),
+            Some(Data::SystemInfo(system)) => self.system = Some(system),
+            Some(Data::CpuInfo(cpus)) => self.cpus = Some(cpus),
+            Some(Data::TraceUuid(uuid)) => self.uuid = Some((uuid.msb(), uuid.lsb())),
+            _ => (),
+        }
+    }
+
+    // the config fields are deprecated in favour of the TraceUuid packet
+    #[allow(deprecated)]
+    pub fn uuid(&self) -> Option<String> {
+        let from_config = self.config.as_ref().filter(|c| c.trace_uuid_msb.is_some()).map(|c| (c.trace_uuid_msb(), c.trace_uuid_lsb()));
+        self.uuid.or(from_config).map(|(msb, lsb)| format_uuid(msb, lsb))
+    }
+
+    pub fn print(&self) {
+        println!("uuid {}", self.uuid().as_deref().unwrap_or("unknown"));
+
+        if let Some(system) = &self.system {
+            println!("system");
+            if let Some(uts) = &system.utsname {
+                println!("  kernel {} {} {} {}", uts.sysname(), uts.release(), uts.version(), uts.machine());
+            }
+            if let Some(fingerprint) = &system.android_build_fingerprint {
+                println!("  android {} sdk {}", fingerprint, system.android_sdk_version());
+            }
+            if let Some(page_size) = system.page_size {
+                println!("  page size {}", page_size);
+            }
+            if let Some(hz) = system.hz {
+                println!("  hz {}", hz);
+            }
+            if let Some(offset) = system.timezone_off_mins {
+                println!("  timezone {:+}min", offset);
+            }
+            if let Some(version) = &system.tracing_service_version {
+                println!("  tracing service {}", version);
+            }
+        }
+
+        if let Some(cpus) = &self.cpus {
+            println!("cpus");
+            // cpus of a cluster are listed one after the other and look the same
+            let mut first = 0;
+            for (i, cpu) in cpus.cpus.iter().enumerate() {
+                let next = cpus.cpus.get(i + 1);
+                if next.is_some_and(|next| next.processor == cpu.processor && next.frequencies == cpu.frequencies) {
+                    continue;
+                }
+                let range = if first == i { format!("cpu{}", i) } else { format!("cpu{}-{}", first, i) };
+                let frequencies = match (cpu.frequencies.iter().min(), cpu.frequencies.iter().max()) {
+                    (Some(&min), Some(&max)) => format!("{}-{} in {} steps", format_khz(min), format_khz(max), cpu.frequencies.len()),
+                    _ => "no frequencies".to_owned(),
+                };
+                println!("  {:<10} {} {}", range, cpu.processor(), frequencies);
+                first = i + 1;
+            }
+        }
+
+        if let Some(config) = &self.config {
+            println!("config");
+            println!("  duration {}ms", config.duration_ms());
+            for (i, buffer) in config.buffers.iter().enumerate() {
+                println!("  buffer {} {}kB {}", i, buffer.size_kb(), buffer.fill_policy().as_str_name());
+            }
+            for source in &config.data_sources {
+                let Some(source_config) = &source.config else {
+                    continue;
+                };
+                let line = format!("  {} buffer={} {}", source_config.name(), source_config.target_buffer(), source_settings(source_config));
+                println!("{}", line.trim_end());
+            }
+            let rest = other_settings(config);
+            if !rest.is_empty() {
+                println!("  {}", rest);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::perfetto::{AndroidLogConfig, FtraceConfig, HeapprofdConfig};
+
+    #[test]
+    fn settings() {
+        let ftrace = FtraceConfig { ftrace_events: vec!["sched/sched_switch".to_owned(), "power/cpu_idle".to_owned()], buffer_size_kb: Some(2048), ..Default::default() };
+        let config = DataSourceConfig { ftrace_config: Some(ftrace), ..Default::default() };
+        assert_eq!(source_settings(&config), "events=sched/sched_switch,power/cpu_idle buffer_size_kb=2048");
+        let log = AndroidLogConfig { log_ids: vec![AndroidLogId::LidDefault as i32, AndroidLogId::LidEvents as i32], min_prio: Some(AndroidLogPriority::PrioWarn as i32), ..Default::default() };
+        let config = DataSourceConfig { android_log_config: Some(log), ..Default::default() };
+        assert_eq!(source_settings(&config), "log_ids=LID_DEFAULT,LID_EVENTS min_prio=PRIO_WARN");
+        let heapprofd = HeapprofdConfig { process_cmdline: vec!["com.app".to_owned()], sampling_interval_bytes: Some(4096), ..Default::default() };
+        let config = DataSourceConfig { heapprofd_config: Some(heapprofd), trace_duration_ms: Some(100), ..Default::default() };
+        assert_eq!(source_settings(&config), "duration_ms=100 cmdline=com.app sampling_interval_bytes=4096");
+        assert_eq!(source_settings(&DataSourceConfig::default()), "");
+    }
+}
@@ -0,0 +1,21 @@
+//! Reading perfetto traces. The `perfetto-rust` binary is built on top of this,
+//! `reader` decodes packets lazily out of a trace file, `info` pulls out the
+//! config and machine description, and the rest turns packets into a model of
+//! slices and counters in timestamp order.
+
+pub mod ftrace;
+pub mod heap;
+pub mod heap_graph;
+pub mod histogram;
+pub mod info;
+pub mod logcat;
+pub mod model;
+pub mod perfetto;
+pub mod process_stats;
+pub mod profile;
+pub mod reader;
+pub mod sorter;
+pub mod symbols;
+pub mod sys_stats;
+pub mod threads;
+pub mod trace_stats;
@@ -0,0 +1,126 @@
+//! Android logcat from `AndroidLogPacket`s. Log events are timestamped in the
+//! realtime clock, main turns that into the boot clock with the ClockSnapshot
+//! so they go through the sorter with everything else. By default every line
+//! is an instant on the thread's "logcat" track, so it shows up in between the
+//! slices of the thread that logged it, the `logcat` view prints them like
+//! `logcat -v threadtime` instead.
+
+use std::fmt::Write;
+
+use crate::model::Model;
+use crate::perfetto::{android_log_packet::{log_event::arg::Value, LogEvent}, AndroidLogId, AndroidLogPriority};
+
+#[derive(Default)]
+pub struct Logcat {
+    pub threadtime: bool,
+    /// From the SystemInfo packet, logcat prints local time.
+    pub timezone_off_mins: i32,
+}
+
+fn priority(prio: AndroidLogPriority) -> char {
+    match prio {
+        AndroidLogPriority::PrioVerbose => 'V',
+        AndroidLogPriority::PrioDebug => 'D',
+        AndroidLogPriority::PrioInfo => 'I',
+        AndroidLogPriority::PrioWarn => 'W',
+        AndroidLogPriority::PrioError => 'E',
+        AndroidLogPriority::PrioFatal => 'F',
+        AndroidLogPriority::PrioUnspecified | AndroidLogPriority::PrioUnused => '?',
+    }
+}
+
+// MM-DD HH:MM:SS.mmm in local time, from ns since the epoch and the minutes
+// the timezone is ahead of UTC
+fn format_realtime(ns: u64, timezone_off_mins: i32) -> String {
+    let ns = ns as i64 + timezone_off_mins as i64 * 60_000_000_000;
+    let secs = ns.div_euclid(1_000_000_000);
+    let (days, day_secs) = (secs.div_euclid(86400), secs.rem_euclid(86400));
+    // days since 1970-01-01 to a civil date, from Howard Hinnant's algorithm
+    let z = days + 719468;
+    let doe = z.rem_euclid(146097);
+    let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let (hours, minutes, seconds) = (day_secs / 3600, day_secs / 60 % 60, day_secs % 60);
+    format!("{:02}-{:02} {:02}:{:02}:{:02}.{:03}", month, day, hours, minutes, seconds, ns.rem_euclid(1_000_000_000) / 1_000_000)
+}
+
+// binary events only have args, logcat -b events prints them as a list
+fn message(e: &LogEvent) -> String {
+    if e.log_id() != AndroidLogId::LidEvents {
+        return e.message().to_owned();
+    }
+    let mut message = String::from("[");
+    for (i, arg) in e.args.iter().enumerate() {
+        if i > 0 {
+            message.push(',');
+        }
+        match &arg.value {
+            Some(Value::IntValue(v)) => write!(message, "{}", v).unwrap(),
+            Some(Value::FloatValue(v)) => write!(message, "{}", v).unwrap(),
+            Some(Value::StringValue(v)) => message.push_str(v),
+            None => (),
+        }
+    }
+    message.push(']');
+    message
+}
+
+impl Logcat {
+    /// `timestamp` in the boot clock, the event's own is still in realtime.
+    pub fn event(&mut self, model: &mut Model, timestamp: u64, e: &LogEvent) {
+        let (pid, tid) = (e.pid() as u32, e.tid() as u32);
+        model.threads.tgid_seen(tid, timestamp, pid);
+        // binary events are all info
+        let prio = match e.log_id() {
+            AndroidLogId::LidEvents => 'I',
+            _ => priority(e.prio()),
+        };
+        let time = format_realtime(e.timestamp(), self.timezone_off_mins);
+        // multi-line messages are a line each, with the same header
+        for line in message(e).trim_end_matches('\n').split('\n') {
+            let line = format!("{} {:5} {:5} {} {:<8}: {}", time, pid, tid, prio, e.tag(), line);
+            if self.threadtime {
+                println!("{}", line);
+            } else {
+                model.instant(tid, "logcat", timestamp, &line);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::perfetto::android_log_packet::log_event::Arg;
+
+    #[test]
+    fn realtime() {
+        assert_eq!(format_realtime(0, 0), "01-01 00:00:00.000");
+        assert_eq!(format_realtime(1_700_000_000_123_456_789, 0), "11-14 22:13:20.123");
+        // leap days and the turn of the century
+        assert_eq!(format_realtime(1_709_208_000_000_000_000, 0), "02-29 12:00:00.000");
+        assert_eq!(format_realtime(951_868_800_000_000_000, 0), "03-01 00:00:00.000");
+        assert_eq!(format_realtime(946_684_799_999_999_999, 0), "12-31 23:59:59.999");
+    }
+
+    #[test]
+    fn timezone() {
+        assert_eq!(format_realtime(1_700_000_000_123_456_789, 120), "11-15 00:13:20.123");
+        assert_eq!(format_realtime(1_700_000_000_123_456_789, -330), "11-14 16:43:20.123");
+        // across new year and before the epoch
+        assert_eq!(format_realtime(946_684_799_999_999_999, 60), "01-01 00:59:59.999");
+        assert_eq!(format_realtime(0, -60), "12-31 23:00:00.000");
+    }
+
+    #[test]
+    fn binary_events() {
+        let arg = |value| Arg { value: Some(value), ..Default::default() };
+        let e = LogEvent { log_id: Some(AndroidLogId::LidEvents as i32), args: vec![arg(Value::IntValue(1)), arg(Value::StringValue("a b".to_owned()))], ..Default::default() };
+        assert_eq!(message(&e), "[1,a b]");
+        let e = LogEvent { log_id: Some(AndroidLogId::LidDefault as i32), message: Some("text".to_owned()), ..Default::default() };
+        assert_eq!(message(&e), "text");
+    }
+}
@@ -1,51 +1,284 @@
-use std::{cell::OnceCell, collections::{hash_map::Entry, HashMap}, env, fs::File, io::{Read, Result}};
-mod perfetto;
-use perfetto::{ftrace_event::Event, trace_packet, track_event, Trace, TracePacketDefaults};
-use prost::Message;
-use perfetto::trace_packet::Data::*;
+use std::{collections::HashMap, env, fs::File, io::{self, Read, Write}, path::PathBuf, process};
+use perfetto_rust::{ftrace::Ftrace, heap::Heaps, heap_graph::HeapGraphs, info::TraceInfo, logcat::Logcat, model::Model, perfetto, process_stats, profile, symbols, sys_stats};
+use perfetto::{android_log_packet::LogEvent, network_packet_bundle::PacketContext, profiling::CpuMode, track_event, FtraceEvent, GpuRenderStageEvent, NetworkPacketEvent, ProcessStats, StreamingAllocation, StreamingFree, SysStats};
+use perfetto::trace_packet::{Data::*, OptionalTrustedPacketSequenceId};
+use perfetto_rust::reader::Packets;
+use perfetto_rust::sorter::{Sorter, Source};
+use perfetto_rust::symbols::{Symbolizer, Symbols};
+use perfetto_rust::threads::{self, Process};
+use perfetto_rust::trace_stats::Health;
 
 use crate::perfetto::track_event::NameField;
 
+// how far back in time a packet may arrive and still be merged in order
+const DEFAULT_REORDER_WINDOW_MS: u64 = 2000;
+
 struct Track {
     tid: i32,
     has_parent: bool,
-    name: OnceCell<String>,
+    // goes in the track column of the output
+    name: String,
     stack: Vec<(u64, String)>
 }
 
-impl Track {
-    fn output_marker(&self, start: u64, end: u64, value: &str) {
-        let name =  self.name.get_or_init(|| value.split_whitespace().next().unwrap().to_owned());
-        println!("{} {} {} {} {}", self.tid, name, start, end, value);
+enum Phase {
+    Begin,
+    End,
+    Instant,
+}
+
+/// Everything that goes through the sorter. Track events carry their own
+/// timestamp in the monotonic clock, the sort key is always the boot clock.
+enum TimelineEvent {
+    // with the name of its kernel function, if it has one
+    Ftrace { event: Box<FtraceEvent>, function: Option<String> },
+    Track { uuid: u64, timestamp: u64, phase: Phase, name: Option<String> },
+    GpuRenderStage { event: Box<GpuRenderStageEvent>, queue: String, stage: String },
+    // `length` covers all the packets when a bundle aggregates them
+    NetworkPacket { event: Box<NetworkPacketEvent>, length: u64 },
+    ProcessStats(Box<ProcessStats>),
+    SysStats(Box<SysStats>),
+    // a resolved stack sample, frames root first
+    Sample { tid: u32, kind: String, frames: Vec<profile::StackFrame> },
+    StreamingAllocation(Box<StreamingAllocation>),
+    StreamingFree(Box<StreamingFree>),
+    Log(Box<LogEvent>),
+}
+
+struct Timeline {
+    model: Model,
+    tracks: HashMap<u64, Track>,
+    ftrace: Ftrace,
+    profile: profile::Profile,
+    heaps: Heaps,
+    logcat: Logcat,
+    symbols: Symbols,
+    // the last timestamp handed out by the sorter, in the boot clock
+    end: u64,
+}
+
+impl Timeline {
+    fn event(&mut self, timestamp: u64, source: Source, event: TimelineEvent) {
+        self.end = self.end.max(timestamp);
+        match event {
+            TimelineEvent::Ftrace { event, function } => {
+                let cpu = match source {
+                    Source::Cpu(cpu) => cpu,
+                    Source::Sequence(_) => unreachable!(),
+                };
+                self.ftrace.event(&mut self.model, timestamp, cpu, *event, function);
+            },
+            TimelineEvent::GpuRenderStage { event, queue, stage } => self.ftrace.gpu.render_stage(&mut self.model, timestamp, *event, queue, stage),
+            TimelineEvent::NetworkPacket { event, length } => self.ftrace.network.packet(&mut self.model, timestamp, &event, length),
+            TimelineEvent::ProcessStats(stats) => process_stats::process_stats(&mut self.model, timestamp, *stats),
+            TimelineEvent::SysStats(stats) => sys_stats::sys_stats(&mut self.model, timestamp, *stats),
+            TimelineEvent::Sample { tid, kind, frames } => self.profile.sample(&mut self.model, &self.symbols, timestamp, tid, &kind, frames),
+            TimelineEvent::StreamingAllocation(e) => self.heaps.streaming_allocation(&mut self.model, timestamp, sequence(source), &e),
+            TimelineEvent::Log(e) => self.logcat.event(&mut self.model, timestamp, &e),
+            TimelineEvent::StreamingFree(e) => self.heaps.streaming_free(&mut self.model, timestamp, sequence(source), &e),
+            TimelineEvent::Track { uuid, timestamp, phase, name } => {
+                let track = self.tracks.get_mut(&uuid).unwrap();
+                match phase {
+                    Phase::Begin => track.stack.push((timestamp, name.unwrap())),
+                    Phase::Instant => {
+                        self.model.slice_mono(track.tid as u32, &track.name, timestamp, timestamp, &name.unwrap());
+                    },
+                    Phase::End => {
+                        if let Some((start_time, name)) = track.stack.pop() {
+                            self.model.slice_mono(track.tid as u32, &track.name, start_time, timestamp, &name);
+                        } else {
+                            eprintln!("missing start")
+                        }
+                    },
+                }
+            },
+        }
+    }
+}
+
+/// What gets printed. By default that's a line per slice, instant and counter
+/// sample, other views print a report once the whole trace has been read.
+#[derive(Clone, Copy, PartialEq)]
+enum View {
+    Lines,
+    Io,
+    TracePipe,
+    Workqueue,
+    Memory,
+    Syscalls,
+    Power,
+    Funcgraph,
+    Network,
+    Kvm,
+    SysStats,
+    Stats,
+    Info,
+    Profile,
+    Heap,
+    JavaHeap,
+    Symbolize,
+    Logcat,
+    Processes,
+}
+
+impl View {
+    fn parse(name: &str) -> Option<View> {
+        match name {
+            "lines" => Some(View::Lines),
+            "io" => Some(View::Io),
+            "trace_pipe" => Some(View::TracePipe),
+            "workqueue" => Some(View::Workqueue),
+            "memory" => Some(View::Memory),
+            "syscalls" => Some(View::Syscalls),
+            "power" => Some(View::Power),
+            "funcgraph" => Some(View::Funcgraph),
+            "network" => Some(View::Network),
+            "kvm" => Some(View::Kvm),
+            "sysstats" => Some(View::SysStats),
+            "stats" => Some(View::Stats),
+            "info" => Some(View::Info),
+            "profile" => Some(View::Profile),
+            "heap" => Some(View::Heap),
+            "java_heap" => Some(View::JavaHeap),
+            "symbolize" => Some(View::Symbolize),
+            "logcat" => Some(View::Logcat),
+            "processes" => Some(View::Processes),
+            _ => None,
+        }
+    }
+}
+
+fn sequence(source: Source) -> u32 {
+    match source {
+        Source::Sequence(sequence_id) => sequence_id,
+        Source::Cpu(_) => unreachable!(),
     }
 }
+
+// PROCESS_RENDERER -> renderer
+fn chrome_process_type(name: &str) -> String {
+    name.trim_start_matches("PROCESS_").to_lowercase()
+}
+
+fn usage() -> ! {
+    eprintln!("usage: perfetto-rust [--reorder-window-ms N] [--symbol-path DIR]... [view] <trace>");
+    eprintln!("views:");
+    eprintln!("  lines       one line per slice, instant and counter sample (default)");
+    eprintln!("  io          block and filesystem latency histograms");
+    eprintln!("  trace_pipe  every ftrace event in the kernel's trace_pipe format");
+    eprintln!("  workqueue   queue to execute latency histograms per work function");
+    eprintln!("  memory      reclaim, compaction, kills and rss counters, then peak rss per process");
+    eprintln!("  syscalls    count and latency per syscall");
+    eprintln!("  power       suspend/resume, wakelocks and clocks only");
+    eprintln!("  funcgraph   kernel call stacks from function_graph, folded for flame graphs");
+    eprintln!("  network     traffic per interface and uid, and tcp connections only");
+    eprintln!("  kvm         host time per vcpu exit reason and hypervisor time per call");
+    eprintln!("  sysstats    meminfo, vmstat and other system counters over time");
+    eprintln!("  stats       buffer and ftrace stats, and whether any data was lost");
+    eprintln!("  info        trace config, uuid, and the machine it was recorded on");
+    eprintln!("  profile     cpu profile samples per thread and stack, folded for flame graphs");
+    eprintln!("  heap        heapprofd call trees per process and dump, and what grew between dumps");
+    eprintln!("  java_heap   live objects and retained size per class of java heap dumps, and what retains the most");
+    eprintln!("  logcat      android log lines like logcat -v threadtime");
+    eprintln!("  processes   every process with its parent, uid, cmdline and when it was seen");
+    eprintln!("  symbolize   write the trace with symbols from --symbol-path appended to stdout");
+    process::exit(1);
+}
+
 fn main() {
+    let mut positional = Vec::new();
+    let mut reorder_window_ms = None;
+    let mut symbol_paths = Vec::new();
+    let mut args = env::args().skip(1);
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--reorder-window-ms" => {
+                reorder_window_ms = Some(args.next().and_then(|v| v.parse().ok()).unwrap_or_else(|| usage()));
+            },
+            // directories of unstripped binaries for frames without symbols
+            "--symbol-path" => symbol_paths.push(PathBuf::from(args.next().unwrap_or_else(|| usage()))),
+            _ => positional.push(arg),
+        }
+    }
+    let (view, path) = match positional.as_slice() {
+        [path] => (View::Lines, path),
+        [view, path] => (View::parse(view).unwrap_or_else(|| usage()), path),
+        _ => usage(),
+    };
 
-    // read in the trace to a fec
-    let mut file = File::open(env::args().nth(1).unwrap()).unwrap();
+    // read in the trace to a vec, packets are decoded one at a time from it
+    let mut file = File::open(path).unwrap();
     let mut buffer = Vec::new();
     file.read_to_end(&mut buffer).unwrap();
-    let trace = Trace::decode(buffer.as_slice()).unwrap();
-    //dbg!(&trace);
+    if view == View::Info {
+        TraceInfo::read(&buffer).unwrap().print();
+        return;
+    }
+    // the trace's own symbols are picked up as it's read
+    let mut symbols = Symbols::default();
+    if !symbol_paths.is_empty() || view == View::Symbolize {
+        let frames = symbols::unsymbolized(&buffer).unwrap();
+        let modules = Symbolizer::new(&symbol_paths).symbolize(&frames);
+        let symbolized: usize = modules.iter().map(|m| m.address_symbols.len()).sum();
+        eprintln!("symbolized {} of {} frames", symbolized, frames.len());
+        if view == View::Symbolize {
+            io::stdout().write_all(&symbols::append(&buffer, modules)).unwrap();
+            return;
+        }
+        for module in &modules {
+            symbols.add(module);
+        }
+    }
 
-    let mut chrome_time = 0;
     let mut current_chrome_time = 0;
-    let mut boot_to_mono = 0;
-    let mut ftrace_thread_state = HashMap::new();
-    let mut tracks: HashMap<u64, Track> = HashMap::new();
-    let mut ftrace_events = Vec::new();
+    // logcat is timestamped in the realtime clock
+    let mut realtime_to_boot = None;
     let mut event_names = HashMap::new();
+    // NetworkPacketBundle contexts by (sequence, iid)
+    let mut packet_contexts = HashMap::new();
+    // per sequence: interned frames and callstacks, the perf timebase, and
+    // the thread and last timestamp in us of chrome's sampler
+    let mut interned: HashMap<u32, profile::Interned> = HashMap::new();
+    let mut perf_timebases = HashMap::new();
+    let mut sampled_threads = HashMap::new();
     let mut default_track_uuid = 0;
-    let mut first = true;
     let default_trace_clock_id = 6;
-    let mut default_timestamp_clock_id = None;
+    // TracePacketDefaults.timestamp_clock_id per sequence
+    let mut default_timestamp_clock_ids = HashMap::new();
+    let mut sorter = Sorter::new(reorder_window_ms.unwrap_or(DEFAULT_REORDER_WINDOW_MS) * 1_000_000);
+    let mut timeline = Timeline { model: Model::default(), tracks: HashMap::new(), ftrace: Ftrace::default(), profile: Default::default(), heaps: Heaps::default(), logcat: Logcat::default(), symbols, end: 0 };
+    timeline.model.quiet = !matches!(view, View::Lines | View::Memory | View::Power | View::Network);
+    timeline.model.track_prefix = match view {
+        View::Memory => Some("mem."),
+        View::Power => Some("power."),
+        View::Network => Some("net."),
+        _ => None,
+    };
+    timeline.ftrace.trace_pipe = view == View::TracePipe;
+    timeline.logcat.threadtime = view == View::Logcat;
+    let mut health = Health::default();
+    let mut heap_graphs = HeapGraphs::default();
 
-    for packet in trace.packet {
+    for packet in Packets::new(&buffer) {
+        let packet = packet.unwrap();
+        let sequence_id = match packet.optional_trusted_packet_sequence_id {
+            Some(OptionalTrustedPacketSequenceId::TrustedPacketSequenceId(id)) => id,
+            None => 0,
+        };
+        health.sequence_packet(sequence_id, &packet);
+        // SEQ_INCREMENTAL_STATE_CLEARED
+        if packet.sequence_flags() & 1 != 0 {
+            interned.remove(&sequence_id);
+            timeline.ftrace.kernel_symbols.retain(|(id, _), _| *id != sequence_id);
+            timeline.ftrace.gpu.specifications.retain(|(id, _), _| *id != sequence_id);
+            packet_contexts.retain(|(id, _), _| *id != sequence_id);
+        }
         if let Some(trace_packet_defaults) = packet.trace_packet_defaults {
             if let Some(timestamp_clock_id) = trace_packet_defaults.timestamp_clock_id {
-                assert_eq!(timestamp_clock_id, 64);
-                default_timestamp_clock_id = Some(timestamp_clock_id);
-                eprintln!("timestamp_clock_id: {:?}", timestamp_clock_id);
+                default_timestamp_clock_ids.insert(sequence_id, timestamp_clock_id);
+            }
+            if let Some(timebase) = trace_packet_defaults.perf_sample_defaults.and_then(|defaults| defaults.timebase) {
+                perf_timebases.insert(sequence_id, timebase.name().to_owned());
             }
             if let Some(track_event_defaults) = trace_packet_defaults.track_event_defaults {
                 if let Some(track_uuid) = track_event_defaults.track_uuid {
@@ -53,32 +286,77 @@ fn main() {
                 }
             }
         }
+        if let Some(interned_data) = packet.interned_data {
+            let tables = interned.entry(sequence_id).or_default();
+            tables.add(&interned_data);
+            for frame_symbols in &interned_data.profiled_frame_symbols {
+                timeline.symbols.add_frame_symbols(sequence_id, frame_symbols.frame_iid(), tables.frame_symbol_lines(frame_symbols));
+            }
+            for name in interned_data.event_names {
+                event_names.insert(name.iid(), name.name().to_owned());
+            }
+            for specification in interned_data.gpu_specifications {
+                timeline.ftrace.gpu.specifications.insert((sequence_id, specification.iid()), specification.name().to_owned());
+            }
+            for context in interned_data.packet_context {
+                if let (Some(iid), Some(ctx)) = (context.iid, context.ctx) {
+                    packet_contexts.insert((sequence_id, iid), ctx);
+                }
+            }
+            for symbol in interned_data.kernel_symbols {
+                let name = String::from_utf8_lossy(symbol.str()).into_owned();
+                timeline.ftrace.kernel_symbols.insert((sequence_id, symbol.iid()), name);
+            }
+        }
         if let Some(data) = packet.data {
+            let clock_id = packet.timestamp_clock_id.or(default_timestamp_clock_ids.get(&sequence_id).copied()).unwrap_or(default_trace_clock_id);
             if let Some(timestamp) = packet.timestamp {
-                let clock_id = packet.timestamp_clock_id.or(default_timestamp_clock_id).unwrap_or(default_trace_clock_id);
                 if clock_id == 64 {
                     current_chrome_time += timestamp;
                 }
             }
+            // the packet timestamp in the boot clock, for packets other than
+            // track events, which keep theirs in the monotonic clock
+            let boot_timestamp = packet.timestamp.and_then(|timestamp| {
+                match clock_id {
+                    6 => Some(timestamp),
+                    3 => Some(timestamp + timeline.model.boot_to_mono),
+                    // chrome's incremental clock is kept in the monotonic clock
+                    64 => Some(current_chrome_time + timeline.model.boot_to_mono),
+                    _ => None,
+                }
+            });
             match data {
+                // data can reach the file up to a flush period after it was
+                // written, unless asked for a window
+                TraceConfig(config) => {
+                    if let (None, Some(flush_period_ms)) = (reorder_window_ms, config.flush_period_ms) {
+                        sorter.widen(2 * flush_period_ms as u64 * 1_000_000);
+                    }
+                },
                 ClockSnapshot(clock_snapshot) => {
                     let mut boot_time = 0;
-                    let mut mono_time = 0; 
+                    let mut mono_time = 0;
+                    let mut realtime = 0;
                     for clock in clock_snapshot.clocks {
                         match clock.clock_id.unwrap() {
+                            1 => realtime = clock.timestamp.unwrap(),
                             6 => boot_time = clock.timestamp.unwrap(),
                             3 => mono_time = clock.timestamp.unwrap(),
                             64 => {
                                 assert!(clock.is_incremental());
-                                chrome_time = clock.timestamp.unwrap();
+                                let mut chrome_time = clock.timestamp.unwrap();
                                 if chrome_time == boot_time {
-                                    chrome_time = chrome_time - boot_to_mono;
+                                    chrome_time -= timeline.model.boot_to_mono;
                                 }
                                 current_chrome_time = chrome_time;
                             },
                             _ => (),
                         }
                     }
+                    if realtime != 0 && boot_time != 0 {
+                        realtime_to_boot = Some(realtime.saturating_sub(boot_time));
+                    }
                     // only compute the difference if we have both
                     if boot_time != 0 && mono_time != 0 {
                         if boot_time < mono_time {
@@ -87,19 +365,176 @@ fn main() {
                             // the difference between them is small and just '0'
                             // as our conversion difference.
                             assert!((mono_time - boot_time) < 1000);
-                            boot_to_mono = 0;   
+                            timeline.model.boot_to_mono = 0;
                         } else {
-                            boot_to_mono = boot_time - mono_time;
+                            timeline.model.boot_to_mono = boot_time - mono_time;
                         }
                     }
                 },
                 FtraceEvents(ftrace_event_bundle) => {
+                    let cpu = ftrace_event_bundle.cpu.unwrap_or(0);
+                    if ftrace_event_bundle.lost_events() {
+                        health.ftrace_lost_events(cpu);
+                    }
+                    for error in &ftrace_event_bundle.error {
+                        health.ftrace_error(error.status.unwrap_or_default());
+                    }
                     for e in ftrace_event_bundle.event {
-                        //let timestamp = e.timestamp.unwrap();
-                        //let pid = e.pid.unwrap();
-                        //dbg!(&e);
                         let timestamp = e.timestamp.unwrap();
-                        ftrace_events.push((e, timestamp));
+                        let function = timeline.ftrace.function(sequence_id, &e);
+                        sorter.push(Source::Cpu(cpu), timestamp, TimelineEvent::Ftrace { event: Box::new(e), function });
+                    }
+                },
+                GpuRenderStageEvent(e) => {
+                    if let Some(timestamp) = boot_timestamp {
+                        let (queue, stage) = timeline.ftrace.gpu.render_stage_names(sequence_id, &e);
+                        sorter.push(Source::Sequence(sequence_id), timestamp, TimelineEvent::GpuRenderStage { event: Box::new(e), queue, stage });
+                    }
+                },
+                NetworkPacket(e) => {
+                    if let Some(timestamp) = boot_timestamp {
+                        let length = e.length().into();
+                        sorter.push(Source::Sequence(sequence_id), timestamp, TimelineEvent::NetworkPacket { event: Box::new(e), length });
+                    }
+                },
+                NetworkPacketBundle(mut bundle) => {
+                    let event = match bundle.packet_context.take() {
+                        Some(PacketContext::Iid(iid)) => packet_contexts.get(&(sequence_id, iid)).cloned(),
+                        Some(PacketContext::Ctx(ctx)) => Some(ctx),
+                        None => None,
+                    };
+                    if let (Some(timestamp), Some(event)) = (boot_timestamp, event) {
+                        if bundle.packet_timestamps.is_empty() {
+                            // aggregated, only totals
+                            let length = bundle.total_length();
+                            sorter.push(Source::Sequence(sequence_id), timestamp, TimelineEvent::NetworkPacket { event: Box::new(event), length });
+                        } else {
+                            // timestamps are relative to the packet's
+                            for (delta, length) in bundle.packet_timestamps.iter().zip(&bundle.packet_lengths) {
+                                let event = TimelineEvent::NetworkPacket { event: Box::new(event.clone()), length: (*length).into() };
+                                sorter.push(Source::Sequence(sequence_id), timestamp + delta, event);
+                            }
+                        }
+                    }
+                },
+                ProcessStats(stats) => {
+                    if let Some(timestamp) = boot_timestamp {
+                        sorter.push(Source::Sequence(sequence_id), timestamp, TimelineEvent::ProcessStats(Box::new(stats)));
+                    }
+                },
+                ProcessTree(tree) => {
+                    let seen = boot_timestamp.unwrap_or(0);
+                    for process in tree.processes {
+                        let pid = process.pid() as u32;
+                        let ppid = process.ppid.map(|ppid| ppid as u32);
+                        let uid = process.uid.map(|uid| uid as u32);
+                        timeline.model.threads.process_seen(pid, Process { seen, cmdline: process.cmdline, ppid, uid, ..Default::default() });
+                        timeline.model.threads.thread_seen(pid, seen, Some(pid), None);
+                    }
+                    for thread in tree.threads {
+                        timeline.model.threads.thread_seen(thread.tid() as u32, seen, thread.tgid.map(|tgid| tgid as u32), thread.name.as_deref());
+                    }
+                },
+                // the legacy standalone descriptors from before TrackDescriptor
+                ProcessDescriptor(process) => {
+                    let chrome_type = process.chrome_process_type.map(|_| chrome_process_type(process.chrome_process_type().as_str_name()));
+                    let pid = process.pid() as u32;
+                    let seen = boot_timestamp.unwrap_or(0);
+                    timeline.model.threads.process_seen(pid, Process { seen, name: process.process_name, cmdline: process.cmdline, chrome_type, ..Default::default() });
+                },
+                ThreadDescriptor(thread) => {
+                    if let Some(reference) = thread.reference_timestamp_us {
+                        sampled_threads.insert(sequence_id, (thread.tid() as u32, reference));
+                    }
+                    let seen = boot_timestamp.unwrap_or(0);
+                    timeline.model.threads.thread_seen(thread.tid() as u32, seen, thread.pid.map(|pid| pid as u32), thread.thread_name.as_deref());
+                },
+                FtraceStats(stats) => health.ftrace_stats(stats),
+                TraceStats(stats) => health.trace_stats(stats),
+                PerfSample(sample) => {
+                    if sample.kernel_records_lost() > 0 {
+                        health.perf_records_lost(sample.kernel_records_lost());
+                    }
+                    let frames = sample.callstack_iid.and_then(|iid| interned.get(&sequence_id)?.callstack_frames(sequence_id, iid));
+                    if let (Some(timestamp), Some(frames)) = (boot_timestamp, frames) {
+                        let mode = match sample.cpu_mode() {
+                            CpuMode::ModeKernel => "kernel",
+                            CpuMode::ModeUser => "user",
+                            CpuMode::ModeHypervisor => "hypervisor",
+                            CpuMode::ModeGuestKernel => "guest_kernel",
+                            CpuMode::ModeGuestUser => "guest_user",
+                            CpuMode::ModeUnknown => "unknown",
+                        };
+                        let kind = match perf_timebases.get(&sequence_id).filter(|name: &&String| !name.is_empty()) {
+                            Some(timebase) => format!("perf.{}.{}", timebase, mode),
+                            None => format!("perf.{}", mode),
+                        };
+                        let event = TimelineEvent::Sample { tid: sample.tid(), kind, frames };
+                        sorter.push(Source::Sequence(sequence_id), timestamp, event);
+                    }
+                },
+                StreamingProfilePacket(samples) => {
+                    // timestamps are deltas from the previous sample, starting
+                    // at the sequence's ThreadDescriptor, in the monotonic clock
+                    if let Some((tid, timestamp_us)) = sampled_threads.get_mut(&sequence_id) {
+                        for (iid, delta) in samples.callstack_iid.iter().zip(&samples.timestamp_delta_us) {
+                            *timestamp_us += delta;
+                            let Some(frames) = interned.get(&sequence_id).and_then(|i| i.callstack_frames(sequence_id, *iid)) else {
+                                continue;
+                            };
+                            let timestamp = *timestamp_us as u64 * 1000 + timeline.model.boot_to_mono;
+                            let event = TimelineEvent::Sample { tid: *tid, kind: "chrome".to_owned(), frames };
+                            sorter.push(Source::Sequence(sequence_id), timestamp, event);
+                        }
+                    }
+                },
+                HeapGraph(graph) => heap_graphs.heap_graph(sequence_id, boot_timestamp.unwrap_or(0), graph),
+                DeobfuscationMapping(mapping) => heap_graphs.deobfuscation.add(&mapping),
+                AndroidLog(log) => {
+                    if let Some(stats) = &log.stats {
+                        health.logcat_stats(stats);
+                    }
+                    for event in log.events {
+                        // without a snapshot of the realtime clock the lines
+                        // can only go where the packet was written
+                        let timestamp = match realtime_to_boot {
+                            Some(offset) => event.timestamp().saturating_sub(offset),
+                            None => boot_timestamp.unwrap_or(0),
+                        };
+                        sorter.push(Source::Sequence(sequence_id), timestamp, TimelineEvent::Log(Box::new(event)));
+                    }
+                },
+                ProfiledFrameSymbols(frame_symbols) => {
+                    // the names are interned by perf or by heapprofd, whichever
+                    // profiles on this sequence
+                    let tables = interned.get(&sequence_id).or_else(|| timeline.heaps.interned(sequence_id));
+                    if let Some(tables) = tables {
+                        timeline.symbols.add_frame_symbols(sequence_id, frame_symbols.frame_iid(), tables.frame_symbol_lines(&frame_symbols));
+                    }
+                },
+                ModuleSymbols(module) => timeline.symbols.add(&module),
+                ProfilePacket(packet) => timeline.heaps.profile_packet(sequence_id, packet),
+                StreamingAllocation(e) => {
+                    if let Some(timestamp) = boot_timestamp {
+                        sorter.push(Source::Sequence(sequence_id), timestamp, TimelineEvent::StreamingAllocation(Box::new(e)));
+                    }
+                },
+                StreamingFree(e) => {
+                    if let Some(timestamp) = boot_timestamp {
+                        sorter.push(Source::Sequence(sequence_id), timestamp, TimelineEvent::StreamingFree(Box::new(e)));
+                    }
+                },
+                SysStats(stats) => {
+                    if let Some(timestamp) = boot_timestamp {
+                        sorter.push(Source::Sequence(sequence_id), timestamp, TimelineEvent::SysStats(Box::new(stats)));
+                    }
+                },
+                SystemInfo(system_info) => {
+                    if let Some(offset) = system_info.timezone_off_mins {
+                        timeline.logcat.timezone_off_mins = offset;
+                    }
+                    if let Some(machine) = system_info.utsname.as_ref().and_then(|u| u.machine.as_ref()) {
+                        timeline.ftrace.syscalls.set_machine(machine);
                     }
                 },
                 TrackDescriptor(track_descriptor) => {
@@ -107,42 +542,41 @@ fn main() {
                     let uuid = track_descriptor.uuid.unwrap();
                     let mut tid = 0;
 
+                    let mut name = "track";
                     // start with the parent track tid if it exists
                     if let Some(parent_uuid) = track_descriptor.parent_uuid {
-                        if let Some(parent) = tracks.get(&parent_uuid) {
+                        if let Some(parent) = timeline.tracks.get(&parent_uuid) {
                             tid = parent.tid;
                         }
                     }
-                    let mut name = OnceCell::new();
+                    let seen = boot_timestamp.unwrap_or(0);
                     if let Some(process) = track_descriptor.process {
                         tid = process.pid.unwrap();
-                        name.set("Process".to_owned());
-
+                        name = "Process";
+                        let chrome_type = track_descriptor.chrome_process.map(|chrome| chrome_process_type(chrome.process_type().as_str_name()));
+                        let process = Process { seen, name: process.process_name, cmdline: process.cmdline, chrome_type, ..Default::default() };
+                        timeline.model.threads.process_seen(tid as u32, process);
                     }
                     if let Some(thread) = track_descriptor.thread {
                         tid = thread.tid.unwrap();
-                        name.set("Thread".to_owned());
+                        name = "Thread";
+                        timeline.model.threads.thread_seen(tid as u32, seen, thread.pid.map(|pid| pid as u32), thread.thread_name.as_deref());
                     }
-                    // Perfetto seems to use the event name of the first event as the track name
-                    tracks.insert(uuid, Track { tid, name, has_parent: track_descriptor.parent_uuid.is_some(), stack: Vec::new()});
+                    // thread and process names come from the thread table, the
+                    // track column only gets an explicit track name
+                    let name = track_descriptor.name.as_deref().unwrap_or(name).replace(' ', "_");
+                    timeline.tracks.insert(uuid, Track { tid, name, has_parent: track_descriptor.parent_uuid.is_some(), stack: Vec::new()});
 
                 },
-                TrackEvent(track_event) => 'track_event: {
+                TrackEvent(track_event) => {
                     let track_uuid = if let Some(uuid) = track_event.track_uuid {
                         if uuid == 0 {
                             // if the track_uuid is 0, then we'll put this event in the track that matches trusted_pid
-                            if let Some(trusted_pid) = packet.trusted_pid {
-                                // find the track that corresponds to the trusted_pid and has no parent
-                                if let Some((&uuid, _)) = tracks.iter().find(|(_, track)| track.tid == trusted_pid && !track.has_parent) {
-                                    uuid
-                                } else {
-                                    panic!("missing track for trusted_pid {}", trusted_pid);
-                                    break 'track_event;
-                                }
-                            } else {
-                                panic!("no track");
-                                // otherwise drop the event
-                                break 'track_event;
+                            let trusted_pid = packet.trusted_pid.expect("no track");
+                            // find the track that corresponds to the trusted_pid and has no parent
+                            match timeline.tracks.iter().find(|(_, track)| track.tid == trusted_pid && !track.has_parent) {
+                                Some((&uuid, _)) => uuid,
+                                None => panic!("missing track for trusted_pid {}", trusted_pid),
                             }
                         } else {
                              uuid
@@ -151,14 +585,8 @@ fn main() {
                         default_track_uuid
                     };
 
-                    if let Some(interned_data) = packet.interned_data {
-                        for name in interned_data.event_names {
-                            event_names.insert(name.iid(), name.name().to_owned());
-                        }
-                    }
                     if let Some(timestamp) = packet.timestamp {
                         // if the timestamp_clock_id is 64, then we'll use the incremental current_chrome_time
-                        let clock_id = packet.timestamp_clock_id.or(default_timestamp_clock_id).unwrap_or(default_trace_clock_id);
                         let timestamp = if clock_id == 64 {
                             current_chrome_time
                         } else if clock_id == 3 {
@@ -167,111 +595,66 @@ fn main() {
                             panic!("unexpected clock_id {}", clock_id);
                         };
                         let name = match &track_event.name_field {
-                            Some(NameField::NameIid(iid)) => Some(event_names[iid].as_str()),
-                            Some(NameField::Name(name)) => Some(name.as_str()),
+                            Some(NameField::NameIid(iid)) => Some(event_names[iid].clone()),
+                            Some(NameField::Name(name)) => Some(name.clone()),
                             None => None,
                         };
-                        let track = tracks.get_mut(&track_uuid).unwrap();
-                        match track_event.r#type() {
-                            track_event::Type::SliceBegin => {
-                                // initialize the track name if it hasn't already been set
-                                track.name.get_or_init(|| name.unwrap().split_whitespace().next().unwrap().to_owned());
-
-                                track.stack.push((timestamp, name.unwrap().to_owned()));
-                                //println!("{} Begin {:?} {:?}", track.tid, current_chrome_time, name.unwrap());
-
-                            },
-                            track_event::Type::Instant => {
-                                track.output_marker(timestamp, timestamp, name.unwrap());
-                            },
-                            track_event::Type::SliceEnd => {
-                                if let Some((start_time, name)) = track.stack.pop() {
-                                    track.output_marker(start_time, timestamp, &name);
-                                } else {
-                                    eprintln!("missing start")
-                                }
-                            }
+                        let phase = match track_event.r#type() {
+                            track_event::Type::SliceBegin => Some(Phase::Begin),
+                            track_event::Type::Instant => Some(Phase::Instant),
+                            track_event::Type::SliceEnd => Some(Phase::End),
                             track_event::Type::Unspecified => {
-                                if let Some(legacy_event) = track_event.legacy_event {
+                                track_event.legacy_event.and_then(|legacy_event| {
                                     match legacy_event.phase.unwrap() as u8 as char {
-                                        'b' => {
-                                            track.stack.push((timestamp, name.unwrap().to_owned()));
-                                        },
-                                        'e' => {
-                                            if let Some((start_time, name)) = track.stack.pop() {
-                                                track.output_marker(start_time, timestamp, &name);
-                                            } else {
-                                                eprintln!("missing start")
-                                            }
-                                        },
-                                        'n' => {
-                                            track.output_marker(timestamp, timestamp, name.unwrap());
-                                        }
-                                        _ => (),
+                                        'b' => Some(Phase::Begin),
+                                        'e' => Some(Phase::End),
+                                        'n' => Some(Phase::Instant),
+                                        _ => None,
                                     }
-                                }
+                                })
                             }
-                            _ => (),
+                            _ => None,
+                        };
+                        if let Some(phase) = phase {
+                            let event = TimelineEvent::Track { uuid: track_uuid, timestamp, phase, name };
+                            sorter.push(Source::Sequence(sequence_id), timestamp + timeline.model.boot_to_mono, event);
                         }
                     }
-
-                    //println!("{:?}", track_event);
                 },
                 _ => (),
             }
         }
+        sorter.flush(|timestamp, source, event| timeline.event(timestamp, source, event));
     }
-    ftrace_events.sort_by_key(|x| x.1);
-    for (e, timestamp) in &ftrace_events {
-        let timestamp = *timestamp;
-        let pid = e.pid.unwrap();
-        match e.event.as_ref().unwrap() {
-            Event::Print(ftrace_print) => {
-                
-                let buf = ftrace_print.buf.as_ref().unwrap();
-                //println!("{}", timestamp);
-                // See ParseSystraceTracePoint in perfetto for how to parse these things
-
-                // drop new line at the end
-                let buf = &buf[..buf.len()-1];
-                // B|1356|prepareSurfaces
-                let mut pieces = buf.split('|');
-
-                let phase = pieces.nth(0).unwrap().chars().nth(0).unwrap();
-                let tgid: i32 = pieces.nth(0).unwrap().parse().unwrap();
-
-                //println!("{} {} {}", pid, timestamp, buf);
-
-                match ftrace_thread_state.entry(pid) {
-                    Entry::Occupied(o) => {
-                        let state: &mut Vec<(u64, char, &str)> = o.into_mut();
-                        match phase {
-                            'B' => {
-                                let msg = pieces.nth(0).unwrap();
-                                state.push((timestamp, phase, msg));
-                            },
-                            'E' => {
-                                if let Some((start, _, msg)) = state.pop() {
-                                    println!("{} ftrace {} {} {}", pid, start - boot_to_mono, timestamp - boot_to_mono, msg);
-                                } else {
-                                    eprintln!("missing start for {}", timestamp)
-                                }
-                            },
-                            _ => (),
-                        }
-                    },
-                    Entry::Vacant(v) => {
-                        v.insert(vec![(timestamp, phase, buf)]);
-                    }
-                }
-
-                //println!("{}: {} -> {} {}", timestamp - boot_to_mono, buf, phase, tgid);
-            },
-            _ => (),
-        }
-        
+    sorter.finish(|timestamp, source, event| timeline.event(timestamp, source, event));
+    if sorter.late > 0 {
+        eprintln!("{} events arrived too late to be sorted in and are out of order, try a larger --reorder-window-ms", sorter.late);
+    }
+    if view != View::Stats {
+        health.warn();
     }
-}  
+    match view {
+        View::Lines | View::TracePipe | View::Power | View::Network | View::Logcat => (),
+        View::Memory => process_stats::print_rss_summary(&timeline.model, timeline.model.to_mono(timeline.end)),
+        View::Io => timeline.ftrace.block.print_latencies(),
+        View::Workqueue => timeline.ftrace.workqueue.print_latencies(),
+        View::Syscalls => timeline.ftrace.syscalls.print_summary(),
+        View::Funcgraph => timeline.ftrace.funcgraph.print_folded(),
+        View::Kvm => timeline.ftrace.kvm.print_latencies(),
+        View::SysStats => sys_stats::print_table(&timeline.model),
+        View::Stats => health.print_report(),
+        View::Info | View::Symbolize => unreachable!(),
+        View::Profile => timeline.profile.print_folded(&timeline.symbols),
+        View::Heap => timeline.heaps.print(&timeline.model, &timeline.symbols),
+        View::Processes => threads::print_processes(&timeline.model),
+        View::JavaHeap => {
+            heap_graphs.finish();
+            for dump in &heap_graphs.dumps {
+                dump.print(&timeline.model, &heap_graphs.deobfuscation);
+            }
+        },
+    }
+}
 
 /*fn main() -> Result<()> {
     prost_build::compile_protos(&["../../src/perfetto/protos/perfetto/trace/perfetto_trace.proto"], &["../../src/perfetto/protos/perfetto/trace/"])?;
@@ -0,0 +1,70 @@
+use std::collections::BTreeMap;
+
+use crate::threads::Threads;
+
+/// What the parsers produce. Slices and instants are printed as soon as they
+/// are complete, counter tracks are also kept around so they can be looked at
+/// once the whole trace has been read.
+///
+/// Timestamps passed in are in the boot clock (the ftrace clock), output is in
+/// the monotonic clock to line up with the track events.
+///
+/// Slice lines are `tid comm process(pid) track start end name`, with the
+/// names the thread and its process had at the start of the slice.
+///
+/// Printing can be turned off with `quiet` for views that only want the
+/// aggregated data, or limited to tracks starting with `track_prefix`.
+#[derive(Default)]
+pub struct Model {
+    pub quiet: bool,
+    pub track_prefix: Option<&'static str>,
+    pub boot_to_mono: u64,
+    pub counters: BTreeMap<String, Vec<(u64, f64)>>,
+    pub threads: Threads,
+}
+
+impl Model {
+    pub fn to_mono(&self, timestamp: u64) -> u64 {
+        timestamp.saturating_sub(self.boot_to_mono)
+    }
+
+    pub fn prints(&self, track: &str) -> bool {
+        !self.quiet && self.track_prefix.is_none_or(|prefix| track.starts_with(prefix))
+    }
+
+    /// `tid` 0 for slices that don't belong to any thread.
+    pub fn slice(&mut self, tid: u32, track: &str, start: u64, end: u64, name: &str) {
+        if !self.prints(track) {
+            return;
+        }
+        let (comm, process) = self.threads.label(tid, start);
+        println!("{} {} {} {} {} {} {}", tid, comm, process, track, self.to_mono(start), self.to_mono(end), name);
+    }
+
+    /// For track events, which are already in the monotonic clock.
+    pub fn slice_mono(&mut self, tid: u32, track: &str, start: u64, end: u64, name: &str) {
+        self.slice(tid, track, start + self.boot_to_mono, end + self.boot_to_mono, name);
+    }
+
+    pub fn instant(&mut self, tid: u32, track: &str, timestamp: u64, name: &str) {
+        self.slice(tid, track, timestamp, timestamp, name);
+    }
+
+    /// An arrow from a point on one thread to a point on another.
+    pub fn flow(&mut self, from_tid: u32, from: u64, to_tid: u32, to: u64, name: &str) {
+        if !self.prints("flow") {
+            return;
+        }
+        let (from_comm, _) = self.threads.label(from_tid, from);
+        let (to_comm, _) = self.threads.label(to_tid, to);
+        println!("flow {} {} {} {} {} {} {}", from_tid, from_comm, self.to_mono(from), to_tid, to_comm, self.to_mono(to), name);
+    }
+
+    pub fn counter(&mut self, track: String, timestamp: u64, value: f64) {
+        let timestamp = self.to_mono(timestamp);
+        if self.prints(&track) {
+            println!("counter {} {} {}", track, timestamp, value);
+        }
+        self.counters.entry(track).or_default().push((timestamp, value));
+    }
+}
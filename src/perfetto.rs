@@ -1 +1,2 @@
-include!("perfetto.protos.rs");
\ No newline at end of file
+#![allow(clippy::all, dead_code)]
+include!("perfetto.protos.rs");
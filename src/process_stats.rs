@@ -0,0 +1,126 @@
+//! ProcessStats polls of /proc/pid/status as per process counters, on the same
+//! "mem." tracks rss_stat and oom_score_adj_update use, and a summary of
+//! what each process used over the trace for the `memory` view.
+
+use std::collections::BTreeSet;
+
+use crate::model::Model;
+use crate::perfetto::ProcessStats;
+
+pub fn process_stats(model: &mut Model, timestamp: u64, stats: ProcessStats) {
+    for process in stats.processes {
+        let pid = process.pid();
+        let kb = [
+            ("mem.rss", process.vm_rss_kb),
+            ("mem.rss.anon", process.rss_anon_kb),
+            ("mem.rss.file", process.rss_file_kb),
+            ("mem.rss.shmem", process.rss_shmem_kb),
+            ("mem.swap", process.vm_swap_kb),
+        ];
+        // rss_stat has these in bytes
+        for (track, value) in kb {
+            if let Some(value) = value {
+                model.counter(format!("{}.{}", track, pid), timestamp, (value * 1024) as f64);
+            }
+        }
+        if let Some(oom_score_adj) = process.oom_score_adj {
+            model.counter(format!("mem.oom_score_adj.{}", pid), timestamp, oom_score_adj as f64);
+        }
+        // cpu time is only there for the whole process, not per thread
+        if let Some(user) = process.runtime_user_mode {
+            model.counter(format!("cpu.runtime.user.{}", pid), timestamp, user as f64);
+        }
+        if let Some(kernel) = process.runtime_kernel_mode {
+            model.counter(format!("cpu.runtime.kernel.{}", pid), timestamp, kernel as f64);
+        }
+    }
+}
+
+// rss_stat only has the parts, summed up at every change with the last
+// value of the others
+fn rss_from_parts(model: &Model, pid: u32) -> Vec<(u64, f64)> {
+    let mut changes: Vec<(u64, usize, f64)> = Vec::new();
+    for (i, part) in ["mem.rss.anon", "mem.rss.file", "mem.rss.shmem"].iter().enumerate() {
+        let samples = model.counters.get(&format!("{}.{}", part, pid)).into_iter().flatten();
+        changes.extend(samples.map(|(ts, v)| (*ts, i, *v)));
+    }
+    changes.sort_by_key(|(ts, i, _)| (*ts, *i));
+    let mut parts = [0.; 3];
+    let mut rss: Vec<(u64, f64)> = Vec::new();
+    for (ts, i, v) in changes {
+        parts[i] = v;
+        let total = parts.iter().sum();
+        match rss.last_mut() {
+            Some(last) if last.0 == ts => last.1 = total,
+            _ => rss.push((ts, total)),
+        }
+    }
+    rss
+}
+
+// every sample counts for as long as it was current, the last one until the
+// end of the trace
+fn time_weighted_mean(samples: &[(u64, f64)], end: u64) -> f64 {
+    let (first, last) = match (samples.first(), samples.last()) {
+        (Some(first), Some(last)) => (first.0, last),
+        _ => return 0.,
+    };
+    let duration = end.max(last.0) - first;
+    if duration == 0 {
+        return last.1;
+    }
+    let ends = samples.iter().skip(1).map(|(ts, _)| *ts).chain([end.max(last.0)]);
+    samples.iter().zip(ends).map(|((ts, v), next)| v * (next - ts) as f64).sum::<f64>() / duration as f64
+}
+
+/// Peak and mean of the sampled rss of every process, biggest peak first.
+/// That's ProcessStats' VmRSS when there is one, otherwise anon + file +
+/// shmem from rss_stat. `end` is the end of the trace in the monotonic clock,
+/// the mean is weighted by how long each sample was current.
+pub fn print_rss_summary(model: &Model, end: u64) {
+    let mut pids = BTreeSet::new();
+    for track in model.counters.range("mem.rss".to_owned().."mem.rss/".to_owned()).map(|(track, _)| track) {
+        if let Some(Ok(pid)) = track.rsplit_once('.').map(|(_, pid)| pid.parse::<u32>()) {
+            pids.insert(pid);
+        }
+    }
+    let mut rows = Vec::new();
+    for pid in pids {
+        let samples = match model.counters.get(&format!("mem.rss.{}", pid)) {
+            Some(samples) => samples.clone(),
+            None => rss_from_parts(model, pid),
+        };
+        let peak = samples.iter().map(|(_, v)| *v).fold(0., f64::max);
+        let mean = time_weighted_mean(&samples, end);
+        // counters are kept in the monotonic clock
+        let last = samples.last().map_or(0, |(ts, _)| ts + model.boot_to_mono);
+        let name = model.threads.process_name(pid, last).unwrap_or("<...>");
+        rows.push((peak, mean, pid, name.to_owned()));
+    }
+    if rows.is_empty() {
+        println!("no rss in this trace, it needs process_stats polling or the rss_stat ftrace event");
+        return;
+    }
+    rows.sort_by(|a, b| b.0.total_cmp(&a.0));
+    println!("{:>8} {:<32} {:>12} {:>12}", "pid", "process", "peak_rss_kb", "mean_rss_kb");
+    for (peak, mean, pid, name) in rows {
+        println!("{:>8} {:<32} {:>12} {:>12}", pid, name, (peak / 1024.) as u64, (mean / 1024.) as u64);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn mean_is_weighted_by_time() {
+        // 100 for 10ns, then 400 for 30ns until the end
+        assert_eq!(time_weighted_mean(&[(0, 100.), (10, 400.)], 40), 325.);
+        // a short spike barely moves it
+        assert_eq!(time_weighted_mean(&[(0, 100.), (99, 10100.), (100, 100.)], 200), 150.);
+        // the end can't be before the last sample
+        assert_eq!(time_weighted_mean(&[(0, 100.), (10, 300.)], 5), 100.);
+        assert_eq!(time_weighted_mean(&[(10, 100.)], 10), 100.);
+        assert_eq!(time_weighted_mean(&[], 10), 0.);
+    }
+}
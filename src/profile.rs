@@ -0,0 +1,214 @@
+//! Stack samples from perf (`PerfSample`) and chrome's in-process sampler
+//! (`StreamingProfilePacket`). Both only carry callstack iids, the frames,
+//! mappings and names behind them are interned per sequence. Every sample is
+//! an instant on the thread's "profile" track, named by its stack, and the
+//! samples per stack are folded up per thread for flame graphs.
+//!
+//! Frames without a function name are kept as they are and only named when
+//! printed, by the `ProfiledFrameSymbols` of the sequence or the trace's
+//! `ModuleSymbols`, which can come anywhere in the trace, see symbols.rs.
+
+use std::borrow::Cow;
+use std::collections::{BTreeMap, HashMap};
+
+use crate::model::Model;
+use crate::perfetto::{Callstack, Frame, InternedData, InternedString, Mapping, ProfiledFrameSymbols};
+use crate::symbols::{self, Symbols};
+
+fn string(bytes: &Option<Vec<u8>>) -> String {
+    String::from_utf8_lossy(bytes.as_deref().unwrap_or_default()).into_owned()
+}
+
+/// A frame the producer couldn't name: the build id of its binary if there
+/// is one, its path, and the pc relative to it.
+#[derive(Clone, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct Unsymbolized {
+    pub build_id: Option<String>,
+    pub path: String,
+    pub rel_pc: u64,
+}
+
+/// A frame of a sample, either named by the producer or still waiting for
+/// symbols, with where in which binary it is in case there never are any.
+#[derive(Clone, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum StackFrame {
+    Named(String),
+    Unsymbolized { sequence_id: u32, iid: u64, binary: Unsymbolized, fallback: String },
+}
+
+/// The interned profiling data of one packet sequence.
+#[derive(Default)]
+pub struct Interned {
+    function_names: HashMap<u64, String>,
+    build_ids: HashMap<u64, String>,
+    mapping_paths: HashMap<u64, String>,
+    source_paths: HashMap<u64, String>,
+    // ProfilePacket's single table for all of the above, as is since build
+    // ids are bytes
+    strings: HashMap<u64, Vec<u8>>,
+    mappings: HashMap<u64, Mapping>,
+    frames: HashMap<u64, Frame>,
+    // frame iids, root first
+    callstacks: HashMap<u64, Vec<u64>>,
+}
+
+impl Interned {
+    pub fn add(&mut self, data: &InternedData) {
+        let tables = [(&data.function_names, &mut self.function_names), (&data.mapping_paths, &mut self.mapping_paths), (&data.source_paths, &mut self.source_paths)];
+        for (strings, table) in tables {
+            for s in strings {
+                table.insert(s.iid(), string(&s.str));
+            }
+        }
+        self.add_build_ids(&data.build_ids);
+        self.add_tables(&data.mappings, &data.frames, &data.callstacks);
+    }
+
+    /// The lines of a frame's symbols, innermost inlined function first, with
+    /// the names interned on this sequence.
+    pub fn frame_symbol_lines(&self, frame_symbols: &ProfiledFrameSymbols) -> Vec<String> {
+        let lines = frame_symbols.function_name_id.iter().enumerate().map(|(i, function)| {
+            let function = self.name(&self.function_names, *function).unwrap_or(Cow::Borrowed("?"));
+            let file = frame_symbols.file_name_id.get(i).and_then(|id| self.name(&self.source_paths, *id));
+            symbols::format_line(&function, file.as_deref(), frame_symbols.line_number.get(i).copied())
+        });
+        lines.collect()
+    }
+
+    /// heapprofd's ProfilePacket has the same tables, with a single string
+    /// table for names, paths and build ids.
+    pub fn add_profile(&mut self, strings: &[InternedString], mappings: &[Mapping], frames: &[Frame], callstacks: &[Callstack]) {
+        for s in strings {
+            self.strings.insert(s.iid(), s.str.clone().unwrap_or_default());
+        }
+        self.add_tables(mappings, frames, callstacks);
+    }
+
+    // from the table of its kind, or ProfilePacket's
+    fn name<'a>(&'a self, table: &'a HashMap<u64, String>, id: u64) -> Option<Cow<'a, str>> {
+        match table.get(&id) {
+            Some(name) => Some(Cow::Borrowed(name)),
+            None => self.strings.get(&id).map(|bytes| String::from_utf8_lossy(bytes)),
+        }
+    }
+
+    fn add_build_ids(&mut self, build_ids: &[InternedString]) {
+        for build_id in build_ids {
+            self.build_ids.insert(build_id.iid(), symbols::hex_build_id(build_id.str.as_deref().unwrap_or_default()));
+        }
+    }
+
+    fn add_tables(&mut self, mappings: &[Mapping], frames: &[Frame], callstacks: &[Callstack]) {
+        for mapping in mappings {
+            self.mappings.insert(mapping.iid(), mapping.clone());
+        }
+        for frame in frames {
+            self.frames.insert(frame.iid(), frame.clone());
+        }
+        for callstack in callstacks {
+            self.callstacks.insert(callstack.iid(), callstack.frame_ids.clone());
+        }
+    }
+
+    /// The path of a mapping, e.g. /system/lib64/libc.so
+    pub fn mapping_path(&self, mapping: &Mapping) -> String {
+        let parts: Vec<_> = mapping.path_string_ids.iter().map(|id| self.name(&self.mapping_paths, *id).unwrap_or(Cow::Borrowed("?"))).collect();
+        parts.join("/")
+    }
+
+    pub fn mapping(&self, iid: u64) -> Option<&Mapping> {
+        self.mappings.get(&iid)
+    }
+
+    pub fn build_id(&self, mapping: &Mapping) -> Option<Cow<'_, str>> {
+        let id = mapping.build_id?;
+        match self.build_ids.get(&id) {
+            Some(build_id) => Some(Cow::Borrowed(build_id)),
+            None => self.strings.get(&id).map(|bytes| Cow::Owned(symbols::hex_build_id(bytes))),
+        }
+    }
+
+    pub fn frame(&self, iid: u64) -> Option<&Frame> {
+        self.frames.get(&iid)
+    }
+
+    fn function_name(&self, frame: &Frame) -> Option<Cow<'_, str>> {
+        frame.function_name_id.and_then(|id| self.name(&self.function_names, id)).filter(|name| !name.is_empty())
+    }
+
+    /// What's needed to symbolize a frame, if it needs it.
+    pub fn unsymbolized(&self, iid: u64) -> Option<Unsymbolized> {
+        let frame = self.frames.get(&iid)?;
+        if self.function_name(frame).is_some() {
+            return None;
+        }
+        let mapping = self.mappings.get(&frame.mapping_id?)?;
+        let build_id = self.build_id(mapping).filter(|id| !id.is_empty()).map(Cow::into_owned);
+        Some(Unsymbolized { build_id, path: self.mapping_path(mapping), rel_pc: frame.rel_pc() })
+    }
+
+    /// A frame as far as this sequence knows, symbols can still name it.
+    pub fn stack_frame(&self, sequence_id: u32, iid: u64) -> StackFrame {
+        let Some(frame) = self.frames.get(&iid) else {
+            return StackFrame::Named(format!("frame{}", iid));
+        };
+        if let Some(name) = self.function_name(frame) {
+            return StackFrame::Named(name.into_owned());
+        }
+        // where in which binary it is, for when nothing symbolizes it
+        let (binary, fallback) = match frame.mapping_id.and_then(|id| self.mappings.get(&id)) {
+            Some(mapping) => {
+                let path = self.mapping_path(mapping);
+                let name = match path.rsplit('/').next().filter(|name| !name.is_empty()) {
+                    Some(name) => name.to_owned(),
+                    None => self.build_id(mapping).map_or_else(|| "?".to_owned(), Cow::into_owned),
+                };
+                (self.unsymbolized(iid), name)
+            },
+            None => (None, "?".to_owned()),
+        };
+        let fallback = format!("{}+{:#x}", fallback, frame.rel_pc());
+        match binary {
+            Some(binary) => StackFrame::Unsymbolized { sequence_id, iid, binary, fallback },
+            None => StackFrame::Named(fallback),
+        }
+    }
+
+    /// Frame iids of a callstack, root first.
+    pub fn callstack(&self, iid: u64) -> Option<&[u64]> {
+        self.callstacks.get(&iid).map(Vec::as_slice)
+    }
+
+    pub fn callstack_frames(&self, sequence_id: u32, iid: u64) -> Option<Vec<StackFrame>> {
+        Some(self.callstack(iid)?.iter().map(|&frame| self.stack_frame(sequence_id, frame)).collect())
+    }
+}
+
+#[derive(Default)]
+pub struct Profile {
+    // samples per `comm(tid)` and stack, named once the whole trace is read
+    folded: BTreeMap<(String, Vec<StackFrame>), u64>,
+}
+
+impl Profile {
+    /// `kind` says where the sample came from and what mode the cpu was in.
+    /// The instant is named with the symbols seen so far.
+    pub fn sample(&mut self, model: &mut Model, symbols: &Symbols, timestamp: u64, tid: u32, kind: &str, frames: Vec<StackFrame>) {
+        if model.prints("profile") {
+            model.instant(tid, "profile", timestamp, &format!("{} {}", kind, symbols.stack_names(&frames).join(";")));
+        }
+        let comm = model.threads.comm(tid, timestamp).unwrap_or("<...>").replace(' ', "_");
+        *self.folded.entry((format!("{}({})", comm, tid), frames)).or_default() += 1;
+    }
+
+    /// Sample counts per thread and stack in the folded flame graph format.
+    pub fn print_folded(&self, symbols: &Symbols) {
+        let mut folded: BTreeMap<String, u64> = BTreeMap::new();
+        for ((thread, frames), samples) in &self.folded {
+            *folded.entry(format!("{};{}", thread, symbols.stack_names(frames).join(";"))).or_default() += samples;
+        }
+        for (path, samples) in folded {
+            println!("{} {}", path, samples);
+        }
+    }
+}
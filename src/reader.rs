@@ -0,0 +1,67 @@
+use prost::{bytes::Buf, encoding::{decode_key, decode_varint, WireType}, DecodeError, Message};
+
+use crate::perfetto::TracePacket;
+
+/// Decodes the packets of a `Trace` one at a time instead of materializing the
+/// whole `Trace` message, so that only the packet being looked at is alive.
+pub struct Packets<'a> {
+    buf: &'a [u8],
+}
+
+impl<'a> Packets<'a> {
+    pub fn new(buf: &'a [u8]) -> Self {
+        Packets { buf }
+    }
+
+    fn next_packet(&mut self) -> Result<Option<TracePacket>, DecodeError> {
+        while self.buf.has_remaining() {
+            let (tag, wire_type) = decode_key(&mut self.buf)?;
+            match wire_type {
+                WireType::LengthDelimited => {
+                    let len = decode_varint(&mut self.buf)? as usize;
+                    if len > self.buf.len() {
+                        return Err(DecodeError::new("truncated packet"));
+                    }
+                    let (data, rest) = self.buf.split_at(len);
+                    self.buf = rest;
+                    // Trace.packet is field 1, anything else is skipped
+                    if tag == 1 {
+                        return TracePacket::decode(data).map(Some);
+                    }
+                }
+                WireType::Varint => {
+                    decode_varint(&mut self.buf)?;
+                }
+                WireType::ThirtyTwoBit => self.skip(4)?,
+                WireType::SixtyFourBit => self.skip(8)?,
+                WireType::StartGroup | WireType::EndGroup => {
+                    return Err(DecodeError::new("unexpected group in trace"));
+                }
+            }
+        }
+        Ok(None)
+    }
+
+    fn skip(&mut self, len: usize) -> Result<(), DecodeError> {
+        if len > self.buf.len() {
+            return Err(DecodeError::new("truncated field"));
+        }
+        self.buf = &self.buf[len..];
+        Ok(())
+    }
+}
+
+impl Iterator for Packets<'_> {
+    type Item = Result<TracePacket, DecodeError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        match self.next_packet() {
+            Ok(packet) => packet.map(Ok),
+            Err(e) => {
+                // stop after the first error, the rest of the buffer can't be trusted
+                self.buf = &[];
+                Some(Err(e))
+            }
+        }
+    }
+}
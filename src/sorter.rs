@@ -0,0 +1,193 @@
+use std::{cmp::Reverse, collections::{BinaryHeap, HashMap, VecDeque}};
+
+/// Where an event came from. Every source is expected to be (mostly) sorted on
+/// its own: the kernel writes ftrace events per cpu in order, and a packet
+/// sequence is written by a single thread.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum Source {
+    Cpu(u32),
+    Sequence(u32),
+}
+
+/// Streaming k-way merge of the per-source event streams.
+///
+/// Events are queued per source and only handed out once they are older than
+/// the newest timestamp seen minus `window`, which bounds how late a packet can
+/// show up and still be put in the right place. Anything arriving after events
+/// newer than it were already handed out is still handed out with the next
+/// flush, out of order, and counted in `late`. Dropping it would break up
+/// begin/end pairs of track events, which only need to be in order per track.
+pub struct Sorter<T> {
+    window: u64,
+    sources: HashMap<Source, usize>,
+    queues: Vec<(Source, VecDeque<(u64, T)>)>,
+    // (timestamp of the queue head, queue index); entries whose timestamp no
+    // longer matches the head of their queue are stale and skipped
+    heads: BinaryHeap<Reverse<(u64, usize)>>,
+    max_timestamp: u64,
+    last_emitted: u64,
+    // arrived too late to be sorted in
+    overdue: VecDeque<(u64, Source, T)>,
+    pub late: u64,
+}
+
+impl<T> Sorter<T> {
+    pub fn new(window: u64) -> Self {
+        Sorter {
+            window,
+            sources: HashMap::new(),
+            queues: Vec::new(),
+            heads: BinaryHeap::new(),
+            max_timestamp: 0,
+            last_emitted: 0,
+            overdue: VecDeque::new(),
+            late: 0,
+        }
+    }
+
+    pub fn push(&mut self, source: Source, timestamp: u64, event: T) {
+        if timestamp < self.last_emitted {
+            self.late += 1;
+            self.overdue.push_back((timestamp, source, event));
+            return;
+        }
+        self.max_timestamp = self.max_timestamp.max(timestamp);
+        let queues = &mut self.queues;
+        let index = *self.sources.entry(source).or_insert_with(|| {
+            queues.push((source, VecDeque::new()));
+            queues.len() - 1
+        });
+        let queue = &mut self.queues[index].1;
+        match queue.back() {
+            Some(&(last, _)) if timestamp < last => {
+                // out of order within the source, keep the queue sorted
+                let pos = queue.partition_point(|&(t, _)| t <= timestamp);
+                queue.insert(pos, (timestamp, event));
+                if pos == 0 {
+                    self.heads.push(Reverse((timestamp, index)));
+                }
+            }
+            Some(_) => queue.push_back((timestamp, event)),
+            None => {
+                queue.push_back((timestamp, event));
+                self.heads.push(Reverse((timestamp, index)));
+            }
+        }
+    }
+
+    /// A larger window, never a smaller one.
+    pub fn widen(&mut self, window: u64) {
+        self.window = self.window.max(window);
+    }
+
+    /// Hands out, in timestamp order, every event that is outside of the
+    /// reorder window, after the ones that came too late for that.
+    pub fn flush(&mut self, f: impl FnMut(u64, Source, T)) {
+        let watermark = self.max_timestamp.saturating_sub(self.window);
+        self.pop_until(watermark, f);
+    }
+
+    /// Hands out everything that is left, for the end of the trace.
+    pub fn finish(&mut self, f: impl FnMut(u64, Source, T)) {
+        self.pop_until(u64::MAX, f);
+    }
+
+    fn pop_until(&mut self, watermark: u64, mut f: impl FnMut(u64, Source, T)) {
+        for (timestamp, source, event) in self.overdue.drain(..) {
+            f(timestamp, source, event);
+        }
+        while let Some(&Reverse((timestamp, index))) = self.heads.peek() {
+            if timestamp > watermark {
+                break;
+            }
+            self.heads.pop();
+            let (source, queue) = &mut self.queues[index];
+            match queue.front() {
+                Some(&(head, _)) if head == timestamp => (),
+                _ => continue,
+            }
+            let (timestamp, event) = queue.pop_front().unwrap();
+            if let Some(&(next, _)) = queue.front() {
+                self.heads.push(Reverse((next, index)));
+            }
+            self.last_emitted = timestamp;
+            f(timestamp, *source, event);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn drain(sorter: &mut Sorter<&'static str>, finish: bool) -> Vec<(u64, &'static str)> {
+        let mut events = Vec::new();
+        let f = |timestamp, _, event| events.push((timestamp, event));
+        if finish {
+            sorter.finish(f);
+        } else {
+            sorter.flush(f);
+        }
+        events
+    }
+
+    #[test]
+    fn merges_sources() {
+        let mut sorter = Sorter::new(0);
+        sorter.push(Source::Cpu(0), 10, "a");
+        sorter.push(Source::Cpu(0), 30, "c");
+        sorter.push(Source::Cpu(1), 20, "b");
+        sorter.push(Source::Sequence(1), 5, "start");
+        assert_eq!(drain(&mut sorter, true), [(5, "start"), (10, "a"), (20, "b"), (30, "c")]);
+    }
+
+    #[test]
+    fn sorts_within_a_source() {
+        let mut sorter = Sorter::new(100);
+        sorter.push(Source::Sequence(1), 20, "b");
+        sorter.push(Source::Sequence(1), 30, "c");
+        sorter.push(Source::Sequence(1), 10, "a");
+        sorter.push(Source::Sequence(1), 25, "b2");
+        assert_eq!(drain(&mut sorter, true), [(10, "a"), (20, "b"), (25, "b2"), (30, "c")]);
+    }
+
+    #[test]
+    fn flush_keeps_the_window() {
+        let mut sorter = Sorter::new(10);
+        sorter.push(Source::Cpu(0), 10, "a");
+        sorter.push(Source::Cpu(0), 15, "b");
+        sorter.push(Source::Cpu(1), 25, "c");
+        assert_eq!(drain(&mut sorter, false), [(10, "a"), (15, "b")]);
+        // still in the window, so it goes before c
+        sorter.push(Source::Cpu(0), 18, "d");
+        assert_eq!(drain(&mut sorter, false), []);
+        assert_eq!(drain(&mut sorter, true), [(18, "d"), (25, "c")]);
+        assert_eq!(sorter.late, 0);
+    }
+
+    #[test]
+    fn hands_out_late_events() {
+        let mut sorter = Sorter::new(10);
+        sorter.push(Source::Cpu(0), 10, "a");
+        sorter.push(Source::Cpu(1), 30, "b");
+        assert_eq!(drain(&mut sorter, false), [(10, "a")]);
+        sorter.push(Source::Cpu(2), 5, "late");
+        sorter.push(Source::Cpu(2), 10, "same time");
+        assert_eq!(drain(&mut sorter, false), [(5, "late"), (10, "same time")]);
+        sorter.push(Source::Cpu(2), 7, "later");
+        assert_eq!(drain(&mut sorter, true), [(7, "later"), (30, "b")]);
+        assert_eq!(sorter.late, 2);
+    }
+
+    #[test]
+    fn widen() {
+        let mut sorter = Sorter::new(10);
+        sorter.widen(5);
+        sorter.widen(20);
+        sorter.push(Source::Cpu(0), 10, "a");
+        sorter.push(Source::Cpu(1), 25, "b");
+        assert_eq!(drain(&mut sorter, false), []);
+        sorter.push(Source::Cpu(0), 31, "c");
+        assert_eq!(drain(&mut sorter, false), [(10, "a")]);
+    }
+}
@@ -0,0 +1,335 @@
+//! Symbols for frames the producer couldn't name, which only have a pc
+//! relative to a binary with a build id. `ModuleSymbols` packets give them
+//! per build id and address, and are appended to a trace by a symbolizer
+//! after recording. `ProfiledFrameSymbols` give them per frame of a sequence.
+//! Either can come after the samples, so frames are only named when printed.
+//! Without them the binaries can be symbolized here from local directories of
+//! unstripped ELF files, and the resulting `ModuleSymbols` appended to the
+//! trace the same way.
+//!
+//! Every symbolized address is a list of functions, innermost inlined one
+//! first, like llvm-symbolizer prints them.
+
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+use std::fs::{self, File};
+use std::io::Read;
+use std::path::{Path, PathBuf};
+
+use addr2line::object::{self, Object, ObjectSegment, SegmentFlags};
+use prost::{DecodeError, Message};
+
+use crate::perfetto::{trace_packet::{Data, OptionalTrustedPacketSequenceId}, AddressSymbols, Line, ModuleSymbols, Trace, TracePacket};
+use crate::profile::{Interned, StackFrame, Unsymbolized};
+use crate::reader::Packets;
+
+// include/uapi/linux/elf.h
+const PF_X: u32 = 1;
+
+/// Build ids are raw bytes, or already hex from older producers.
+pub fn hex_build_id(bytes: &[u8]) -> String {
+    match std::str::from_utf8(bytes) {
+        Ok(s) if s.bytes().all(|b| b.is_ascii_hexdigit()) => s.to_owned(),
+        _ => bytes.iter().map(|b| format!("{:02x}", b)).collect(),
+    }
+}
+
+/// `function (file:line)`, with whatever is known of where.
+pub fn format_line(function: &str, file: Option<&str>, line: Option<u32>) -> String {
+    match (file.filter(|f| !f.is_empty()), line.filter(|l| *l != 0)) {
+        (Some(file), Some(line)) => format!("{} ({}:{})", function, file, line),
+        (Some(file), None) => format!("{} ({})", function, file),
+        _ => function.to_owned(),
+    }
+}
+
+// mapping paths come without the leading slash when split into parts
+fn normalize_path(path: &str) -> &str {
+    path.trim_start_matches('/')
+}
+
+#[derive(Default)]
+pub struct Symbols {
+    // by hex build id, or by path for binaries without one
+    by_build_id: HashMap<String, HashMap<u64, Vec<String>>>,
+    by_path: HashMap<String, HashMap<u64, Vec<String>>>,
+    // ProfiledFrameSymbols by (sequence, frame iid)
+    frames: HashMap<(u32, u64), Vec<String>>,
+}
+
+impl Symbols {
+    pub fn add(&mut self, module: &ModuleSymbols) {
+        let addresses = match module.build_id.as_deref().filter(|id| !id.is_empty()) {
+            Some(build_id) => self.by_build_id.entry(hex_build_id(build_id.as_bytes())).or_default(),
+            None => self.by_path.entry(normalize_path(module.path()).to_owned()).or_default(),
+        };
+        for address in &module.address_symbols {
+            let lines = address.lines.iter().map(|l| format_line(l.function_name(), l.source_file_name.as_deref(), l.line_number));
+            addresses.insert(address.address(), lines.collect());
+        }
+    }
+
+    /// Lines from `Interned::frame_symbol_lines`.
+    pub fn add_frame_symbols(&mut self, sequence_id: u32, frame_iid: u64, lines: Vec<String>) {
+        self.frames.insert((sequence_id, frame_iid), lines);
+    }
+
+    pub fn lookup(&self, frame: &Unsymbolized) -> Option<&[String]> {
+        let addresses = match &frame.build_id {
+            Some(build_id) => self.by_build_id.get(build_id)?,
+            None => self.by_path.get(normalize_path(&frame.path))?,
+        };
+        addresses.get(&frame.rel_pc).map(Vec::as_slice)
+    }
+
+    /// Symbols for a frame the producer didn't name, innermost first.
+    pub fn resolve(&self, frame: &StackFrame) -> Option<&[String]> {
+        match frame {
+            StackFrame::Named(_) => None,
+            StackFrame::Unsymbolized { sequence_id, iid, binary, .. } => self.frames.get(&(*sequence_id, *iid)).map(Vec::as_slice).or_else(|| self.lookup(binary)),
+        }
+    }
+
+    /// The functions of a frame, root first, so more than one when functions
+    /// were inlined into it.
+    pub fn names(&self, frame: &StackFrame) -> Vec<String> {
+        match (frame, self.resolve(frame)) {
+            (_, Some(lines)) if !lines.is_empty() => lines.iter().rev().cloned().collect(),
+            (StackFrame::Named(name), _) | (StackFrame::Unsymbolized { fallback: name, .. }, _) => vec![name.clone()],
+        }
+    }
+
+    pub fn stack_names(&self, frames: &[StackFrame]) -> Vec<String> {
+        frames.iter().flat_map(|frame| self.names(frame)).collect()
+    }
+}
+
+/// Every frame of the trace without a name or symbols, going through the
+/// interned data of every sequence like the main loop does. Only when asked
+/// to symbolize, since that's another pass over the whole trace.
+pub fn unsymbolized(trace: &[u8]) -> Result<BTreeSet<Unsymbolized>, DecodeError> {
+    let mut interned: HashMap<u32, Interned> = HashMap::new();
+    let mut symbols = Symbols::default();
+    let mut frames = BTreeSet::new();
+    for packet in Packets::new(trace) {
+        let packet = packet?;
+        let sequence_id = match packet.optional_trusted_packet_sequence_id {
+            Some(OptionalTrustedPacketSequenceId::TrustedPacketSequenceId(id)) => id,
+            None => 0,
+        };
+        if packet.sequence_flags() & 1 != 0 {
+            interned.remove(&sequence_id);
+        }
+        let tables = interned.entry(sequence_id).or_default();
+        let mut iids = Vec::new();
+        if let Some(data) = &packet.interned_data {
+            tables.add(data);
+            iids.extend(data.frames.iter().map(|f| f.iid()));
+            for s in &data.profiled_frame_symbols {
+                symbols.add_frame_symbols(sequence_id, s.frame_iid(), tables.frame_symbol_lines(s));
+            }
+        }
+        match &packet.data {
+            Some(Data::ProfilePacket(p)) => {
+                tables.add_profile(&p.strings, &p.mappings, &p.frames, &p.callstacks);
+                iids.extend(p.frames.iter().map(|f| f.iid()));
+            },
+            Some(Data::ProfiledFrameSymbols(s)) => symbols.add_frame_symbols(sequence_id, s.frame_iid(), tables.frame_symbol_lines(s)),
+            Some(Data::ModuleSymbols(module)) => symbols.add(module),
+            _ => (),
+        }
+        frames.extend(iids.into_iter().map(|iid| tables.stack_frame(sequence_id, iid)));
+    }
+    let unsymbolized = frames.into_iter().filter(|frame| symbols.resolve(frame).is_none()).filter_map(|frame| match frame {
+        StackFrame::Unsymbolized { binary, .. } => Some(binary),
+        StackFrame::Named(_) => None,
+    });
+    Ok(unsymbolized.collect())
+}
+
+/// Symbolizes frames with the ELF files under some directories, found by
+/// build id, or by file name for binaries without one.
+pub struct Symbolizer {
+    by_build_id: HashMap<String, PathBuf>,
+    by_name: HashMap<String, PathBuf>,
+}
+
+impl Symbolizer {
+    pub fn new(dirs: &[PathBuf]) -> Symbolizer {
+        let mut symbolizer = Symbolizer { by_build_id: HashMap::new(), by_name: HashMap::new() };
+        let mut pending: Vec<(PathBuf, bool)> = dirs.iter().map(|dir| (dir.clone(), true)).collect();
+        while let Some((path, root)) = pending.pop() {
+            // symlinked directories found while walking aren't followed, they
+            // can make cycles, but the ones given on the command line are
+            let metadata = if root { fs::metadata(&path) } else { fs::symlink_metadata(&path) };
+            let metadata = match metadata {
+                Ok(metadata) => metadata,
+                Err(err) if root => {
+                    eprintln!("can't read symbol path {}: {}", path.display(), err);
+                    continue;
+                },
+                Err(_) => continue,
+            };
+            if metadata.is_dir() {
+                pending.extend(fs::read_dir(&path).into_iter().flatten().flatten().map(|entry| (entry.path(), false)));
+                continue;
+            }
+            if !is_elf(&path) {
+                continue;
+            }
+            let Ok(data) = fs::read(&path) else {
+                continue;
+            };
+            let Ok(file) = object::File::parse(&*data) else {
+                continue;
+            };
+            if let Ok(Some(build_id)) = file.build_id() {
+                symbolizer.by_build_id.entry(hex_build_id(build_id)).or_insert_with(|| path.clone());
+            }
+            if let Some(name) = path.file_name().and_then(|n| n.to_str()) {
+                symbolizer.by_name.entry(name.to_owned()).or_insert_with(|| path.clone());
+            }
+        }
+        symbolizer
+    }
+
+    fn binary(&self, frame: &Unsymbolized) -> Option<&Path> {
+        match &frame.build_id {
+            Some(build_id) => self.by_build_id.get(build_id),
+            None => self.by_name.get(frame.path.rsplit('/').next()?),
+        }
+        .map(PathBuf::as_path)
+    }
+
+    /// One `ModuleSymbols` per build id, or per path for binaries without
+    /// one, since that's what they're looked up by. Frames in binaries that
+    /// weren't found are left alone.
+    pub fn symbolize(&self, frames: &BTreeSet<Unsymbolized>) -> Vec<ModuleSymbols> {
+        let mut by_binary: BTreeMap<&Path, Vec<&Unsymbolized>> = BTreeMap::new();
+        for frame in frames {
+            if let Some(binary) = self.binary(frame) {
+                by_binary.entry(binary).or_default().push(frame);
+            }
+        }
+        let mut modules: BTreeMap<(Option<&str>, String), ModuleSymbols> = BTreeMap::new();
+        for (binary, frames) in by_binary {
+            let address_symbols = match symbolize_binary(binary, &frames) {
+                Ok(address_symbols) => address_symbols,
+                Err(e) => {
+                    eprintln!("can't symbolize {}: {}", binary.display(), e);
+                    continue;
+                },
+            };
+            for (frame, symbols) in frames.iter().zip(address_symbols) {
+                let Some(symbols) = symbols else {
+                    continue;
+                };
+                let path = format!("/{}", normalize_path(&frame.path));
+                let key = match &frame.build_id {
+                    Some(build_id) => (Some(build_id.as_str()), String::new()),
+                    None => (None, path.clone()),
+                };
+                let module = modules.entry(key).or_insert_with(|| ModuleSymbols { path: Some(path), build_id: frame.build_id.clone(), address_symbols: Vec::new() });
+                module.address_symbols.push(symbols);
+            }
+        }
+        modules.into_values().collect()
+    }
+}
+
+// just the first bytes, to not read in everything else in the directories
+fn is_elf(path: &Path) -> bool {
+    let mut magic = [0; 4];
+    File::open(path).and_then(|mut file| file.read_exact(&mut magic)).is_ok() && magic == *b"\x7fELF"
+}
+
+// addresses are relative to where the binary was mapped, the load bias of
+// the first executable segment turns them into addresses in the file. One
+// entry per frame, None for the ones nothing was found for.
+fn symbolize_binary(binary: &Path, frames: &[&Unsymbolized]) -> Result<Vec<Option<AddressSymbols>>, Box<dyn std::error::Error>> {
+    let data = fs::read(binary)?;
+    let file = object::File::parse(&*data)?;
+    let load_bias = file
+        .segments()
+        .find(|s| matches!(s.flags(), SegmentFlags::Elf { p_flags } if p_flags & PF_X != 0))
+        .map_or(0, |s| s.address().wrapping_sub(s.file_range().0));
+    let context = addr2line::Context::new(&file)?;
+    // for binaries without debug info, only the function is known
+    let symbol_map = file.symbol_map();
+    let mut address_symbols = Vec::new();
+    for frame in frames {
+        let address = frame.rel_pc.wrapping_add(load_bias);
+        let mut lines = Vec::new();
+        let symbol = symbol_map.get(address).map(|s| addr2line::demangle_auto(s.name().into(), None).into_owned());
+        let mut inlined = context.find_frames(address).skip_all_loads()?;
+        while let Some(f) = inlined.next()? {
+            let function_name = f.function.as_ref().and_then(|name| name.demangle().ok()).map(|name| name.into_owned());
+            let location = f.location.as_ref();
+            let source_file_name = location.and_then(|l| l.file).map(str::to_owned);
+            lines.push(Line { function_name, source_file_name, line_number: location.and_then(|l| l.line) });
+        }
+        // the symbol table only knows the outermost function
+        match lines.last_mut() {
+            Some(outermost) if outermost.function_name.is_none() => outermost.function_name = symbol,
+            Some(_) => (),
+            None => lines.extend(symbol.map(|name| Line { function_name: Some(name), ..Default::default() })),
+        }
+        address_symbols.push((!lines.is_empty()).then_some(AddressSymbols { address: Some(frame.rel_pc), lines }));
+    }
+    Ok(address_symbols)
+}
+
+/// The trace with the symbols appended, a trace is just its packets one
+/// after the other so that's still a valid one.
+pub fn append(trace: &[u8], modules: Vec<ModuleSymbols>) -> Vec<u8> {
+    let packets = modules.into_iter().map(|module| TracePacket { data: Some(Data::ModuleSymbols(module)), ..Default::default() });
+    let mut out = trace.to_vec();
+    out.extend(Trace { packet: packets.collect() }.encode_to_vec());
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn build_ids() {
+        assert_eq!(hex_build_id(&[0x66, 0x6c, 0xcd, 0x4c]), "666ccd4c");
+        assert_eq!(hex_build_id(&[0xde, 0xad, 0xbe, 0xef]), "deadbeef");
+        assert_eq!(hex_build_id(b"666ccd4c"), "666ccd4c");
+        assert_eq!(hex_build_id(&[]), "");
+    }
+
+    #[test]
+    fn lines() {
+        assert_eq!(format_line("f", Some("a.c"), Some(12)), "f (a.c:12)");
+        assert_eq!(format_line("f", Some("a.c"), None), "f (a.c)");
+        assert_eq!(format_line("f", Some("a.c"), Some(0)), "f (a.c)");
+        assert_eq!(format_line("f", Some(""), Some(12)), "f");
+        assert_eq!(format_line("f", None, Some(12)), "f");
+    }
+
+    fn line(function: &str) -> Line {
+        Line { function_name: Some(function.to_owned()), ..Default::default() }
+    }
+
+    fn frame(build_id: Option<&str>, path: &str, rel_pc: u64) -> StackFrame {
+        let binary = Unsymbolized { build_id: build_id.map(str::to_owned), path: path.to_owned(), rel_pc };
+        StackFrame::Unsymbolized { sequence_id: 1, iid: rel_pc, binary, fallback: format!("lib.so+{:#x}", rel_pc) }
+    }
+
+    #[test]
+    fn names_root_first() {
+        let mut symbols = Symbols::default();
+        // innermost first, like ModuleSymbols has them
+        let address = AddressSymbols { address: Some(0x10), lines: vec![line("inner"), line("outer")] };
+        symbols.add(&ModuleSymbols { path: Some("/lib/a.so".to_owned()), build_id: Some("abcd".to_owned()), address_symbols: vec![address.clone()] });
+        symbols.add(&ModuleSymbols { path: Some("/lib/b.so".to_owned()), address_symbols: vec![address], ..Default::default() });
+        assert_eq!(symbols.names(&frame(Some("abcd"), "lib/other.so", 0x10)), ["outer", "inner"]);
+        assert_eq!(symbols.names(&frame(None, "lib/b.so", 0x10)), ["outer", "inner"]);
+        assert_eq!(symbols.names(&frame(Some("abcd"), "lib/a.so", 0x20)), ["lib.so+0x20"]);
+        assert_eq!(symbols.names(&StackFrame::Named("main".to_owned())), ["main"]);
+        // the sequence's own symbols win
+        symbols.add_frame_symbols(1, 0x10, vec!["own".to_owned()]);
+        assert_eq!(symbols.names(&frame(Some("abcd"), "lib/a.so", 0x10)), ["own"]);
+    }
+}
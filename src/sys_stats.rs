@@ -0,0 +1,142 @@
+//! SysStats polls of /proc/meminfo, /proc/vmstat, /proc/stat and friends as
+//! system wide "sys." counters, named the way the kernel names them, and the
+//! table the `sysstats` view prints from them.
+
+use crate::model::Model;
+use crate::perfetto::{sys_stats::psi_sample::PsiResource, SysStats, VmstatCounters};
+
+// /proc/meminfo names in MeminfoCounters order, from fs/proc/meminfo.c
+const MEMINFO_NAMES: [&str; 34] = [
+    "", "MemTotal", "MemFree", "MemAvailable", "Buffers", "Cached", "SwapCached", "Active", "Inactive", "Active(anon)", "Inactive(anon)",
+    "Active(file)", "Inactive(file)", "Unevictable", "Mlocked", "SwapTotal", "SwapFree", "Dirty", "Writeback", "AnonPages", "Mapped",
+    "Shmem", "Slab", "SReclaimable", "SUnreclaim", "KernelStack", "PageTables", "CommitLimit", "Committed_AS", "VmallocTotal",
+    "VmallocUsed", "VmallocChunk", "CmaTotal", "CmaFree",
+];
+
+fn meminfo_name(key: i32) -> String {
+    match MEMINFO_NAMES.get(key as usize).filter(|name| !name.is_empty()) {
+        Some(name) => name.to_string(),
+        None => format!("meminfo{}", key),
+    }
+}
+
+// the enum names are the /proc/vmstat ones upper cased with a prefix
+fn vmstat_name(key: i32) -> String {
+    match VmstatCounters::try_from(key) {
+        Ok(counter) => counter.as_str_name().trim_start_matches("VMSTAT_").to_lowercase(),
+        Err(_) => format!("vmstat{}", key),
+    }
+}
+
+pub fn sys_stats(model: &mut Model, timestamp: u64, stats: SysStats) {
+    let mut counter = |track: String, value: f64| model.counter(format!("sys.{}", track), timestamp, value);
+    // meminfo is in kB, counters are in bytes like the rest
+    for value in &stats.meminfo {
+        counter(format!("meminfo.{}", meminfo_name(value.key.unwrap_or_default())), (value.value() * 1024) as f64);
+    }
+    for value in &stats.vmstat {
+        counter(format!("vmstat.{}", vmstat_name(value.key.unwrap_or_default())), value.value() as f64);
+    }
+    for cpu in &stats.cpu_stat {
+        let times = [
+            ("user", cpu.user_ns),
+            ("nice", cpu.user_nice_ns),
+            ("system", cpu.system_mode_ns),
+            ("idle", cpu.idle_ns),
+            ("iowait", cpu.io_wait_ns),
+            ("irq", cpu.irq_ns),
+            ("softirq", cpu.softirq_ns),
+        ];
+        for (name, ns) in times {
+            if let Some(ns) = ns {
+                counter(format!("cpu{}.{}_ns", cpu.cpu_id(), name), ns as f64);
+            }
+        }
+    }
+    if let Some(forks) = stats.num_forks {
+        counter("forks".to_owned(), forks as f64);
+    }
+    if let Some(total) = stats.num_irq_total {
+        counter("irq.total".to_owned(), total as f64);
+    }
+    for irq in &stats.num_irq {
+        counter(format!("irq.{}", irq.irq()), irq.count() as f64);
+    }
+    if let Some(total) = stats.num_softirq_total {
+        counter("softirq.total".to_owned(), total as f64);
+    }
+    for softirq in &stats.num_softirq {
+        counter(format!("softirq.{}", softirq.irq()), softirq.count() as f64);
+    }
+    for devfreq in &stats.devfreq {
+        counter(format!("devfreq.{}", devfreq.key()), devfreq.value() as f64);
+    }
+    for (cpu, khz) in stats.cpufreq_khz.iter().enumerate() {
+        counter(format!("cpu{}.freq_khz", cpu), *khz as f64);
+    }
+    for buddy in &stats.buddy_info {
+        for (order, pages) in buddy.order_pages.iter().enumerate() {
+            counter(format!("buddyinfo.{}.{}.order{}", buddy.node(), buddy.zone(), order), *pages as f64);
+        }
+    }
+    for disk in &stats.disk_stat {
+        let values = [
+            ("read_sectors", disk.read_sectors),
+            ("read_time_ms", disk.read_time_ms),
+            ("write_sectors", disk.write_sectors),
+            ("write_time_ms", disk.write_time_ms),
+            ("discard_sectors", disk.discard_sectors),
+            ("discard_time_ms", disk.discard_time_ms),
+            ("flush_count", disk.flush_count),
+            ("flush_time_ms", disk.flush_time_ms),
+        ];
+        for (name, value) in values {
+            if let Some(value) = value {
+                counter(format!("disk.{}.{}", disk.device_name(), name), value as f64);
+            }
+        }
+    }
+    for psi in &stats.psi {
+        let resource = match psi.resource() {
+            PsiResource::Unspecified => continue,
+            resource => resource.as_str_name().trim_start_matches("PSI_RESOURCE_").to_lowercase(),
+        };
+        counter(format!("psi.{}_ns", resource), psi.total_ns() as f64);
+    }
+}
+
+const SPARKS: [char; 8] = ['▁', '▂', '▃', '▄', '▅', '▆', '▇', '█'];
+const SPARK_COLUMNS: usize = 40;
+
+/// A row per "sys." counter with its range and a sparkline of it over the
+/// whole trace, all on the same time axis.
+pub fn print_table(model: &Model) {
+    let tracks: Vec<_> = model.counters.iter().filter(|(track, _)| track.starts_with("sys.")).collect();
+    let samples = tracks.iter().flat_map(|(_, samples)| samples.iter().map(|(ts, _)| *ts));
+    let (Some(start), Some(end)) = (samples.clone().min(), samples.max()) else {
+        return;
+    };
+    let span = (end - start).max(1);
+    println!("{:<48} {:>14} {:>14} {:>14}  over time", "counter", "min", "max", "last");
+    for (track, samples) in tracks {
+        let min = samples.iter().map(|(_, v)| *v).fold(f64::INFINITY, f64::min);
+        let max = samples.iter().map(|(_, v)| *v).fold(f64::NEG_INFINITY, f64::max);
+        // the last value in each column, carried forward through empty ones
+        let mut columns = vec![None; SPARK_COLUMNS];
+        for (ts, value) in samples {
+            let column = ((ts - start) as u128 * (SPARK_COLUMNS - 1) as u128 / span as u128) as usize;
+            columns[column] = Some(*value);
+        }
+        let mut current = None;
+        let spark: String = columns.into_iter().map(|value| {
+            current = value.or(current);
+            match current {
+                None => ' ',
+                Some(_) if max == min => SPARKS[0],
+                Some(v) => SPARKS[((v - min) / (max - min) * (SPARKS.len() - 1) as f64).round() as usize],
+            }
+        }).collect();
+        let last = samples.last().map_or(0., |(_, v)| *v);
+        println!("{:<48} {:>14} {:>14} {:>14}  {}", &track["sys.".len()..], min, max, last, spark);
+    }
+}
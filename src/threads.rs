@@ -0,0 +1,266 @@
+//! Thread and process names over time. Comms change with prctl and exec and
+//! tids get reused once a thread is gone, so every name is kept with when it
+//! was seen and looked up by timestamp. Timestamps are in the boot clock.
+//!
+//! Ftrace events come in in timestamp order, but process trees and
+//! descriptors are read as soon as they show up, so everything is inserted in
+//! order rather than appended.
+
+use std::collections::HashMap;
+
+use crate::model::Model;
+
+// include/uapi/linux/sched.h
+const CLONE_THREAD: u64 = 0x10000;
+
+// one lifetime of a tid, from when it was created (or first seen)
+struct Thread {
+    start: u64,
+    tgid: Option<u32>,
+    comms: Vec<(u64, String)>,
+}
+
+/// What was known about a process when it was `seen`, from an exec, a
+/// ProcessTree or a ProcessDescriptor.
+#[derive(Clone, Default)]
+pub struct Process {
+    pub seen: u64,
+    pub name: Option<String>,
+    pub cmdline: Vec<String>,
+    pub ppid: Option<u32>,
+    pub uid: Option<u32>,
+    // chrome's browser, renderer, gpu and so on
+    pub chrome_type: Option<String>,
+}
+
+impl Process {
+    pub fn name(&self) -> Option<&str> {
+        let from_cmdline = self.cmdline.first().map(|arg0| arg0.rsplit('/').next().unwrap_or(arg0));
+        self.name.as_deref().or(from_cmdline).or(self.chrome_type.as_deref())
+    }
+}
+
+#[derive(Default)]
+pub struct Threads {
+    threads: HashMap<u32, Vec<Thread>>,
+    // without one the name of the main thread is used
+    processes: HashMap<u32, Vec<Process>>,
+}
+
+// the index of the last entry starting at or before timestamp, or the first
+// one if everything only showed up later
+fn index<T>(entries: &[T], timestamp: u64, start: impl Fn(&T) -> u64) -> Option<usize> {
+    let i = entries.partition_point(|e| start(e) <= timestamp);
+    (!entries.is_empty()).then(|| i.saturating_sub(1))
+}
+
+fn at<T>(entries: &[T], timestamp: u64, start: impl Fn(&T) -> u64) -> Option<&T> {
+    entries.get(index(entries, timestamp, start)?)
+}
+
+fn insert<T>(entries: &mut Vec<T>, timestamp: u64, entry: T, start: impl Fn(&T) -> u64) {
+    let i = entries.partition_point(|e| start(e) <= timestamp);
+    entries.insert(i, entry);
+}
+
+impl Threads {
+    fn thread(&self, tid: u32, timestamp: u64) -> Option<&Thread> {
+        at(self.threads.get(&tid)?, timestamp, |t| t.start)
+    }
+
+    fn thread_mut(&mut self, tid: u32, timestamp: u64) -> &mut Thread {
+        let lifetimes = self.threads.entry(tid).or_default();
+        match index(lifetimes, timestamp, |t| t.start) {
+            Some(i) => &mut lifetimes[i],
+            None => {
+                lifetimes.push(Thread { start: timestamp, tgid: None, comms: Vec::new() });
+                &mut lifetimes[0]
+            },
+        }
+    }
+
+    /// A comm seen for a thread, only recorded when it changed.
+    pub fn comm_seen(&mut self, tid: u32, timestamp: u64, comm: &str) {
+        // pid 0 is the idle task of every cpu
+        if tid == 0 || comm.is_empty() {
+            return;
+        }
+        let thread = self.thread_mut(tid, timestamp);
+        if at(&thread.comms, timestamp, |(ts, _)| *ts).is_none_or(|(_, current)| current != comm) {
+            insert(&mut thread.comms, timestamp, (timestamp, comm.to_owned()), |(ts, _)| *ts);
+        }
+    }
+
+    pub fn tgid_seen(&mut self, tid: u32, timestamp: u64, tgid: u32) {
+        self.thread_mut(tid, timestamp).tgid = Some(tgid);
+    }
+
+    /// A thread from a ProcessTree or ThreadDescriptor.
+    pub fn thread_seen(&mut self, tid: u32, timestamp: u64, tgid: Option<u32>, name: Option<&str>) {
+        if let Some(tgid) = tgid {
+            self.tgid_seen(tid, timestamp, tgid);
+        }
+        if let Some(name) = name {
+            self.comm_seen(tid, timestamp, name);
+        }
+    }
+
+    /// task_newtask, a new lifetime for `tid` forked from `parent`.
+    pub fn new_task(&mut self, tid: u32, timestamp: u64, parent: u32, comm: &str, clone_flags: u64) {
+        let tgid = if clone_flags & CLONE_THREAD != 0 {
+            Some(self.tgid(parent, timestamp).unwrap_or(parent))
+        } else {
+            // a fork keeps the parent's name until it execs, and the pid may
+            // have been some other process before
+            let ppid = self.tgid(parent, timestamp).unwrap_or(parent);
+            let name = Some(self.process_name(ppid, timestamp).unwrap_or(comm).to_owned());
+            self.process_seen(tid, Process { seen: timestamp, name, ppid: Some(ppid), ..Default::default() });
+            Some(tid)
+        };
+        let thread = Thread { start: timestamp, tgid, comms: vec![(timestamp, comm.to_owned())] };
+        insert(self.threads.entry(tid).or_default(), timestamp, thread, |t| t.start);
+    }
+
+    /// sched_process_exec, the process takes the name of the binary.
+    pub fn exec(&mut self, tid: u32, timestamp: u64, filename: &str) {
+        let pid = self.tgid(tid, timestamp).unwrap_or(tid);
+        let process = Process { seen: timestamp, cmdline: vec![filename.to_owned()], ..Default::default() };
+        self.process_seen(pid, process);
+    }
+
+    /// Whatever `process` leaves out is carried over from what was known
+    /// before, except that a new name or cmdline replaces both.
+    pub fn process_seen(&mut self, pid: u32, mut process: Process) {
+        let observations = self.processes.entry(pid).or_default();
+        if let Some(previous) = at(observations, process.seen, |p| p.seen) {
+            process.ppid = process.ppid.or(previous.ppid);
+            process.uid = process.uid.or(previous.uid);
+            process.chrome_type = process.chrome_type.or(previous.chrome_type.clone());
+            if process.name.is_none() && process.cmdline.is_empty() {
+                process.name.clone_from(&previous.name);
+                process.cmdline.clone_from(&previous.cmdline);
+            }
+        }
+        insert(observations, process.seen, process, |p| p.seen);
+    }
+
+    /// Every process by pid, with what was known about it each time it was
+    /// seen, in order.
+    pub fn processes(&self) -> impl Iterator<Item = (u32, &[Process])> {
+        self.processes.iter().map(|(pid, observations)| (*pid, observations.as_slice()))
+    }
+
+    pub fn process(&self, pid: u32, timestamp: u64) -> Option<&Process> {
+        at(self.processes.get(&pid)?, timestamp, |p| p.seen)
+    }
+
+    pub fn comm(&self, tid: u32, timestamp: u64) -> Option<&str> {
+        let thread = self.thread(tid, timestamp)?;
+        at(&thread.comms, timestamp, |(ts, _)| *ts).map(|(_, comm)| comm.as_str())
+    }
+
+    pub fn tgid(&self, tid: u32, timestamp: u64) -> Option<u32> {
+        match self.thread(tid, timestamp).and_then(|t| t.tgid) {
+            Some(tgid) => Some(tgid),
+            // a process nobody said anything about the threads of
+            None => self.processes.contains_key(&tid).then_some(tid),
+        }
+    }
+
+    pub fn process_name(&self, pid: u32, timestamp: u64) -> Option<&str> {
+        self.process(pid, timestamp).and_then(Process::name).or_else(|| self.comm(pid, timestamp))
+    }
+
+    /// The comm and `process(pid)` columns of output lines, without spaces.
+    pub fn label(&self, tid: u32, timestamp: u64) -> (String, String) {
+        let comm = match tid {
+            0 => "<idle>",
+            _ => self.comm(tid, timestamp).unwrap_or("<...>"),
+        };
+        let process = match self.tgid(tid, timestamp) {
+            Some(pid) => format!("{}({})", self.process_name(pid, timestamp).unwrap_or("<...>"), pid),
+            None => "<...>".to_owned(),
+        };
+        (comm.replace(' ', "_"), process.replace(' ', "_"))
+    }
+}
+
+/// The process table as it was last seen, with when each process was first
+/// and last seen.
+pub fn print_processes(model: &Model) {
+    let mut processes: Vec<_> = model.threads.processes().collect();
+    processes.sort_by_key(|(pid, _)| *pid);
+    println!("{:>8} {:>8} {:>8} {:>12} {:>12} {:<24} {:<10} cmdline", "pid", "ppid", "uid", "first_seen", "last_seen", "name", "chrome");
+    for (pid, observations) in processes {
+        let (Some(first), Some(last)) = (observations.first(), observations.last()) else {
+            continue;
+        };
+        let or_dash = |value: Option<u32>| value.map_or("-".to_owned(), |v| v.to_string());
+        let name = last.name().unwrap_or("<...>").replace(' ', "_");
+        let chrome = last.chrome_type.as_deref().unwrap_or("-");
+        let (first_seen, last_seen) = (model.to_mono(first.seen), model.to_mono(last.seen));
+        println!("{:>8} {:>8} {:>8} {:>12} {:>12} {:<24} {:<10} {}", pid, or_dash(last.ppid), or_dash(last.uid), first_seen, last_seen, name, chrome, last.cmdline.join(" "));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn rename() {
+        let mut threads = Threads::default();
+        threads.tgid_seen(11, 10, 10);
+        threads.comm_seen(11, 10, "Thread-1");
+        threads.comm_seen(11, 20, "RenderThread");
+        // the same comm again isn't a change
+        threads.comm_seen(11, 30, "RenderThread");
+        assert_eq!(threads.comm(11, 5), Some("Thread-1"));
+        assert_eq!(threads.comm(11, 15), Some("Thread-1"));
+        assert_eq!(threads.comm(11, 20), Some("RenderThread"));
+        assert_eq!(threads.comm(11, 40), Some("RenderThread"));
+        assert_eq!(threads.thread(11, 40).unwrap().comms.len(), 2);
+        assert_eq!(threads.label(11, 15), ("Thread-1".to_owned(), "<...>(10)".to_owned()));
+    }
+
+    #[test]
+    fn reused_tid() {
+        let mut threads = Threads::default();
+        threads.process_seen(10, Process { seen: 0, name: Some("app".to_owned()), ..Default::default() });
+        threads.process_seen(20, Process { seen: 0, name: Some("zygote".to_owned()), ..Default::default() });
+        threads.new_task(11, 10, 10, "worker", CLONE_THREAD);
+        // 11 is gone and comes back as a fork of the zygote
+        threads.new_task(11, 100, 20, "zygote", 0);
+        threads.exec(11, 110, "/system/bin/app2");
+        assert_eq!(threads.comm(11, 50), Some("worker"));
+        assert_eq!(threads.tgid(11, 50), Some(10));
+        assert_eq!(threads.comm(11, 100), Some("zygote"));
+        assert_eq!(threads.tgid(11, 100), Some(11));
+        assert_eq!(threads.process(11, 100).unwrap().ppid, Some(20));
+        // a fork keeps the parent's name until it execs
+        assert_eq!(threads.process_name(11, 105), Some("zygote"));
+        assert_eq!(threads.process_name(11, 110), Some("app2"));
+        assert_eq!(threads.process(11, 110).unwrap().ppid, Some(20));
+    }
+
+    #[test]
+    fn process_tree_before_queued_ftrace() {
+        let mut threads = Threads::default();
+        // the ProcessTree is read right away, the ftrace events from before
+        // it come out of the sorter later
+        threads.process_seen(10, Process { seen: 100, cmdline: vec!["/system/bin/app".to_owned()], uid: Some(1000), ..Default::default() });
+        threads.thread_seen(11, 100, Some(10), Some("tree"));
+        threads.comm_seen(11, 50, "early");
+        threads.exec(10, 60, "/system/bin/launcher");
+        assert_eq!(threads.comm(11, 50), Some("early"));
+        assert_eq!(threads.comm(11, 99), Some("early"));
+        assert_eq!(threads.comm(11, 100), Some("tree"));
+        assert_eq!(threads.tgid(11, 50), Some(10));
+        assert_eq!(threads.process_name(10, 70), Some("launcher"));
+        assert_eq!(threads.process_name(10, 100), Some("app"));
+        // the exec goes in before the tree and takes what it doesn't know
+        // from it
+        assert_eq!(threads.process(10, 70).unwrap().uid, Some(1000));
+        assert_eq!(threads.processes().next().unwrap().1.len(), 2);
+    }
+}
@@ -0,0 +1,264 @@
+//! Whether the trace has everything that happened in it. The tracing service
+//! writes TraceStats at the end and traced_probes writes FtraceStats at the
+//! start and end, with what was lost to full buffers, and packets and bundles
+//! say when something right before them went missing. The `stats` view prints
+//! all of it, every other view gets a warning on stderr if anything was lost.
+
+use std::collections::{BTreeMap, HashSet};
+
+use crate::perfetto::{android_log_packet, ftrace_stats::Phase, trace_stats::FinalFlushOutcome, FtraceCpuStats, FtraceParseStatus, FtraceStats, TracePacket, TraceStats};
+
+#[derive(Default)]
+pub struct Health {
+    ftrace_start: Option<FtraceStats>,
+    ftrace_end: Option<FtraceStats>,
+    trace_stats: Option<TraceStats>,
+    // packets with previous_packet_dropped per sequence, and the sequences
+    // seen so far since their first packet always has it set
+    sequence_losses: BTreeMap<u32, u64>,
+    sequences: HashSet<u32>,
+    // ftrace bundles with lost_events per cpu
+    ftrace_losses: BTreeMap<u32, u64>,
+    ftrace_errors: BTreeMap<String, u64>,
+    // samples the kernel dropped before traced_perf could read them
+    perf_records_lost: u64,
+    // log events logd gave that couldn't be parsed, the stats are totals
+    logcat_failed: u64,
+}
+
+// what was lost on a cpu between the start and end of the trace
+fn cpu_losses(start: Option<&FtraceCpuStats>, end: &FtraceCpuStats) -> [(&'static str, u64); 3] {
+    let delta = |field: fn(&FtraceCpuStats) -> u64| field(end).saturating_sub(start.map_or(0, field));
+    [
+        ("overrun", delta(FtraceCpuStats::overrun)),
+        ("commit_overrun", delta(FtraceCpuStats::commit_overrun)),
+        ("dropped_events", delta(FtraceCpuStats::dropped_events)),
+    ]
+}
+
+impl Health {
+    pub fn ftrace_stats(&mut self, stats: FtraceStats) {
+        match stats.phase() {
+            Phase::StartOfTrace => self.ftrace_start = Some(stats),
+            Phase::EndOfTrace => self.ftrace_end = Some(stats),
+            Phase::Unspecified => (),
+        }
+    }
+
+    pub fn trace_stats(&mut self, stats: TraceStats) {
+        self.trace_stats = Some(stats);
+    }
+
+    pub fn sequence_packet(&mut self, sequence_id: u32, packet: &TracePacket) {
+        let first = self.sequences.insert(sequence_id) || packet.first_packet_on_sequence();
+        if packet.previous_packet_dropped() && !first {
+            *self.sequence_losses.entry(sequence_id).or_default() += 1;
+        }
+    }
+
+    pub fn ftrace_lost_events(&mut self, cpu: u32) {
+        *self.ftrace_losses.entry(cpu).or_default() += 1;
+    }
+
+    pub fn perf_records_lost(&mut self, lost: u64) {
+        self.perf_records_lost += lost;
+    }
+
+    pub fn logcat_stats(&mut self, stats: &android_log_packet::Stats) {
+        self.logcat_failed = self.logcat_failed.max(stats.num_failed());
+    }
+
+    pub fn ftrace_error(&mut self, status: i32) {
+        let name = match FtraceParseStatus::try_from(status) {
+            Ok(status) => status.as_str_name().to_owned(),
+            Err(_) => format!("status{}", status),
+        };
+        *self.ftrace_errors.entry(name).or_default() += 1;
+    }
+
+    /// Everything that means data is missing, one line each.
+    pub fn problems(&self) -> Vec<String> {
+        let mut problems = Vec::new();
+        if let Some(end) = &self.ftrace_end {
+            for cpu in &end.cpu_stats {
+                let start = self.ftrace_start.as_ref().and_then(|s| s.cpu_stats.iter().find(|c| c.cpu == cpu.cpu));
+                for (name, lost) in cpu_losses(start, cpu) {
+                    if lost > 0 {
+                        problems.push(format!("ftrace cpu{} {} {}", cpu.cpu(), name, lost));
+                    }
+                }
+            }
+            if !end.failed_ftrace_events.is_empty() {
+                problems.push(format!("ftrace events that failed to enable: {}", end.failed_ftrace_events.join(", ")));
+            }
+            if end.atrace_errors.as_deref().is_some_and(|e| !e.is_empty()) {
+                problems.push(format!("atrace errors: {}", end.atrace_errors()));
+            }
+        }
+        for (cpu, bundles) in &self.ftrace_losses {
+            problems.push(format!("ftrace cpu{} {} bundles after lost events", cpu, bundles));
+        }
+        for (error, count) in &self.ftrace_errors {
+            problems.push(format!("ftrace {} x{}", error, count));
+        }
+        if self.perf_records_lost > 0 {
+            problems.push(format!("perf records lost {}", self.perf_records_lost));
+        }
+        if self.logcat_failed > 0 {
+            problems.push(format!("logcat events failed to parse {}", self.logcat_failed));
+        }
+        for (sequence, count) in &self.sequence_losses {
+            problems.push(format!("sequence {} dropped packets {} times", sequence, count));
+        }
+        if let Some(stats) = &self.trace_stats {
+            let totals = [
+                ("chunks discarded", stats.chunks_discarded()),
+                ("patches discarded", stats.patches_discarded()),
+                ("invalid packets", stats.invalid_packets()),
+                ("flushes failed", stats.flushes_failed()),
+            ];
+            for (name, count) in totals {
+                if count > 0 {
+                    problems.push(format!("{} {}", name, count));
+                }
+            }
+            if stats.final_flush_outcome() == FinalFlushOutcome::FinalFlushFailed {
+                problems.push("final flush failed".to_owned());
+            }
+            for (i, buffer) in stats.buffer_stats.iter().enumerate() {
+                let counts = [
+                    ("chunks overwritten", buffer.chunks_overwritten()),
+                    ("chunks discarded", buffer.chunks_discarded()),
+                    ("patches failed", buffer.patches_failed()),
+                    ("readaheads failed", buffer.readaheads_failed()),
+                    ("abi violations", buffer.abi_violations()),
+                    ("writer packet loss", buffer.trace_writer_packet_loss()),
+                ];
+                for (name, count) in counts {
+                    if count > 0 {
+                        problems.push(format!("buffer {} {} {}", i, name, count));
+                    }
+                }
+            }
+            if let Some(filter) = stats.filter_stats.as_ref().filter(|f| f.errors() > 0) {
+                problems.push(format!("trace filter errors {}", filter.errors()));
+            }
+        }
+        problems
+    }
+
+    /// For views other than `stats`, only says something when data is missing.
+    pub fn warn(&self) {
+        let problems = self.problems();
+        if problems.is_empty() {
+            return;
+        }
+        eprintln!("!!! WARNING: THIS TRACE IS INCOMPLETE, data was lost while recording:");
+        for problem in problems {
+            eprintln!("!!!   {}", problem);
+        }
+        eprintln!("!!! run the stats view for details");
+    }
+
+    pub fn print_report(&self) {
+        match &self.trace_stats {
+            Some(stats) => {
+                println!("trace stats");
+                println!("  producers connected={} seen={}", stats.producers_connected(), stats.producers_seen());
+                println!("  data sources registered={} seen={}", stats.data_sources_registered(), stats.data_sources_seen());
+                println!("  flushes requested={} succeeded={} failed={}", stats.flushes_requested(), stats.flushes_succeeded(), stats.flushes_failed());
+                for (i, buffer) in stats.buffer_stats.iter().enumerate() {
+                    println!("  buffer {} size={} written={} overwritten={} read={}", i, buffer.buffer_size(), buffer.bytes_written(), buffer.bytes_overwritten(), buffer.bytes_read());
+                    println!("    chunks written={} overwritten={} discarded={} read={} out_of_order={}", buffer.chunks_written(), buffer.chunks_overwritten(), buffer.chunks_discarded(), buffer.chunks_read(), buffer.chunks_committed_out_of_order());
+                    println!("    patches succeeded={} failed={} writer_packet_loss={}", buffer.patches_succeeded(), buffer.patches_failed(), buffer.trace_writer_packet_loss());
+                }
+            },
+            None => println!("no trace stats, the trace may have been cut short"),
+        }
+        match &self.ftrace_end {
+            Some(end) => {
+                println!("ftrace stats");
+                println!("  kernel symbols parsed={} mem={}kB", end.kernel_symbols_parsed(), end.kernel_symbols_mem_kb());
+                for cpu in &end.cpu_stats {
+                    let start = self.ftrace_start.as_ref().and_then(|s| s.cpu_stats.iter().find(|c| c.cpu == cpu.cpu));
+                    let losses: Vec<_> = cpu_losses(start, cpu).iter().map(|(name, lost)| format!("{}={}", name, lost)).collect();
+                    println!("  cpu{} entries={} read_events={} {}", cpu.cpu(), cpu.entries(), cpu.read_events(), losses.join(" "));
+                }
+                if !end.unknown_ftrace_events.is_empty() {
+                    println!("  unknown events: {}", end.unknown_ftrace_events.join(", "));
+                }
+            },
+            None if self.ftrace_start.is_some() => println!("no ftrace stats from the end of the trace"),
+            None => (),
+        }
+        let problems = self.problems();
+        if problems.is_empty() {
+            println!("OK: no data was lost");
+        } else {
+            println!("INCOMPLETE: data was lost while recording");
+            for problem in problems {
+                println!("  {}", problem);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::perfetto::trace_stats::BufferStats;
+
+    fn cpu(cpu: u64, overrun: u64) -> FtraceCpuStats {
+        FtraceCpuStats { cpu: Some(cpu), overrun: Some(overrun), ..Default::default() }
+    }
+
+    fn packet(first: bool, dropped: bool) -> TracePacket {
+        TracePacket { first_packet_on_sequence: Some(first), previous_packet_dropped: Some(dropped), ..Default::default() }
+    }
+
+    #[test]
+    fn complete() {
+        let mut health = Health::default();
+        // the first packet on a sequence always says something was dropped
+        health.sequence_packet(1, &packet(true, true));
+        health.sequence_packet(1, &packet(false, false));
+        health.sequence_packet(2, &packet(false, true));
+        health.ftrace_stats(FtraceStats { phase: Some(Phase::StartOfTrace as i32), cpu_stats: vec![cpu(0, 5)], ..Default::default() });
+        health.ftrace_stats(FtraceStats { phase: Some(Phase::EndOfTrace as i32), cpu_stats: vec![cpu(0, 5)], ..Default::default() });
+        health.trace_stats(TraceStats { buffer_stats: vec![BufferStats::default()], ..Default::default() });
+        assert!(health.problems().is_empty());
+    }
+
+    #[test]
+    fn problems() {
+        let mut health = Health::default();
+        // overruns from before the trace started don't count
+        health.ftrace_stats(FtraceStats { phase: Some(Phase::StartOfTrace as i32), cpu_stats: vec![cpu(0, 5), cpu(1, 0)], ..Default::default() });
+        let end = FtraceStats { phase: Some(Phase::EndOfTrace as i32), cpu_stats: vec![cpu(0, 5), cpu(1, 3)], failed_ftrace_events: vec!["sched/sched_foo".to_owned()], atrace_errors: Some(String::new()), ..Default::default() };
+        health.ftrace_stats(end);
+        health.ftrace_lost_events(2);
+        health.ftrace_lost_events(2);
+        health.ftrace_error(FtraceParseStatus::FtraceStatusPartialPageRead as i32);
+        health.ftrace_error(1000);
+        health.perf_records_lost(7);
+        health.logcat_stats(&android_log_packet::Stats { num_failed: Some(2), ..Default::default() });
+        health.logcat_stats(&android_log_packet::Stats { num_failed: Some(1), ..Default::default() });
+        health.sequence_packet(1, &packet(true, true));
+        health.sequence_packet(1, &packet(false, true));
+        let buffer = BufferStats { chunks_overwritten: Some(4), ..Default::default() };
+        health.trace_stats(TraceStats { buffer_stats: vec![BufferStats::default(), buffer], flushes_failed: Some(1), final_flush_outcome: Some(FinalFlushOutcome::FinalFlushFailed as i32), ..Default::default() });
+        assert_eq!(health.problems(), [
+            "ftrace cpu1 overrun 3",
+            "ftrace events that failed to enable: sched/sched_foo",
+            "ftrace cpu2 2 bundles after lost events",
+            "ftrace FTRACE_STATUS_PARTIAL_PAGE_READ x1",
+            "ftrace status1000 x1",
+            "perf records lost 7",
+            "logcat events failed to parse 2",
+            "sequence 1 dropped packets 1 times",
+            "flushes failed 1",
+            "final flush failed",
+            "buffer 1 chunks overwritten 4",
+        ]);
+    }
+}